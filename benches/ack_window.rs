@@ -0,0 +1,100 @@
+use std::time::Instant;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use tiny_udt::window::AckWindow;
+
+const WINDOW_SIZE: usize = 1024;
+
+/// Previous implementation scanning records from tail to head
+struct LinearAckWindow {
+    items: Vec<(Instant, u32, u32)>,
+    head: usize,
+    tail: usize,
+}
+
+impl LinearAckWindow {
+    fn new() -> Self {
+        let mut items = vec![(Instant::now(), 0, 0); WINDOW_SIZE];
+        items[0].1 = u32::MAX;
+        Self {
+            items,
+            head: 0,
+            tail: 0,
+        }
+    }
+
+    fn store(&mut self, seq_no: u32, data_seq_no: u32) {
+        self.items[self.head] = (Instant::now(), seq_no, data_seq_no);
+        self.head = (self.head + 1) % WINDOW_SIZE;
+        if self.head == self.tail {
+            self.tail = (self.tail + 1) % WINDOW_SIZE;
+        }
+    }
+
+    fn acknowledge(&mut self, seq_no: u32) -> Option<(u32, std::time::Duration)> {
+        let end = if self.head >= self.tail {
+            self.head
+        } else {
+            self.head + WINDOW_SIZE
+        };
+        for i in self.tail..end {
+            let (timestamp, item_seq_no, data_seq_no) = self.items[i % WINDOW_SIZE];
+            if item_seq_no != seq_no {
+                continue;
+            }
+
+            if i + 1 == self.head {
+                self.head = 0;
+                self.tail = 0;
+                self.items[0].1 = u32::MAX;
+            } else {
+                self.tail = (i + 1) % WINDOW_SIZE;
+            }
+            return Some((data_seq_no, timestamp.elapsed()));
+        }
+        None
+    }
+}
+
+/// Keeps `outstanding` ACKs in flight and confirms the oldest one after each new ACK.
+/// ACK-2 for the newest ACK is looked up as well to hit the worst case of the linear scan.
+fn ack_window(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ack_window");
+
+    for outstanding in [16u32, 256, 1000] {
+        group.bench_with_input(
+            BenchmarkId::new("linear", outstanding),
+            &outstanding,
+            |b, &outstanding| {
+                let mut window = LinearAckWindow::new();
+                let mut seq_no = 0u32;
+                b.iter(|| {
+                    window.store(seq_no, seq_no);
+                    window.acknowledge(seq_no.wrapping_sub(outstanding));
+                    window.acknowledge(seq_no.wrapping_add(1));
+                    seq_no = seq_no.wrapping_add(1);
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("indexed", outstanding),
+            &outstanding,
+            |b, &outstanding| {
+                let mut window = AckWindow::new(WINDOW_SIZE).unwrap();
+                let mut seq_no = 0u32;
+                b.iter(|| {
+                    window.store(seq_no, seq_no, Instant::now());
+                    window.acknowledge(seq_no.wrapping_sub(outstanding), Instant::now());
+                    window.acknowledge(seq_no.wrapping_add(1), Instant::now());
+                    seq_no = seq_no.wrapping_add(1);
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, ack_window);
+criterion_main!(benches);
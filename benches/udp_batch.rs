@@ -0,0 +1,71 @@
+use std::net::UdpSocket;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use tiny_udt::batch::{recv_batch, send_batch, PacketBatch};
+
+const BATCH_SIZE: usize = 64;
+const PACKET_SIZE: usize = 1456;
+
+fn sockets() -> (UdpSocket, UdpSocket) {
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    receiver
+        .set_read_timeout(Some(Duration::from_millis(100)))
+        .unwrap();
+    sender.connect(receiver.local_addr().unwrap()).unwrap();
+    (sender, receiver)
+}
+
+fn per_packet(c: &mut Criterion) {
+    let (sender, receiver) = sockets();
+    let packet = [0u8; PACKET_SIZE];
+    let mut buffer = [0u8; PACKET_SIZE];
+
+    let mut group = c.benchmark_group("loopback");
+    group.throughput(Throughput::Bytes((BATCH_SIZE * PACKET_SIZE) as u64));
+    group.bench_function("per_packet", |b| {
+        b.iter(|| {
+            for _ in 0..BATCH_SIZE {
+                sender.send(&packet).unwrap();
+            }
+            for _ in 0..BATCH_SIZE {
+                if receiver.recv_from(&mut buffer).is_err() {
+                    break;
+                }
+            }
+        })
+    });
+    group.finish();
+}
+
+fn batched(c: &mut Criterion) {
+    let (sender, receiver) = sockets();
+    let receiver_addr = receiver.local_addr().unwrap();
+
+    let mut send = PacketBatch::new(BATCH_SIZE, PACKET_SIZE);
+    let mut recv = PacketBatch::new(BATCH_SIZE, PACKET_SIZE);
+    let packet = [0u8; PACKET_SIZE];
+    for _ in 0..BATCH_SIZE {
+        send.push(&packet, receiver_addr);
+    }
+
+    let mut group = c.benchmark_group("loopback");
+    group.throughput(Throughput::Bytes((BATCH_SIZE * PACKET_SIZE) as u64));
+    group.bench_function("batched", |b| {
+        b.iter(|| {
+            send_batch(&sender, &mut send).unwrap();
+            let mut received = 0;
+            while received < BATCH_SIZE {
+                match recv_batch(&receiver, &mut recv) {
+                    Ok(count) => received += count,
+                    Err(_) => break,
+                }
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, per_packet, batched);
+criterion_main!(benches);
@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tiny_udt::packet::MessageDropRequestControlInfo;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(info) = MessageDropRequestControlInfo::deserialize(data) else {
+        return;
+    };
+
+    let mut buffer = [0u8; 64];
+    let serialized = info.serialize(&mut buffer).unwrap();
+    assert_eq!(
+        MessageDropRequestControlInfo::deserialize(serialized).unwrap(),
+        info
+    );
+});
@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tiny_udt::packet::NakControlInfo;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(info) = NakControlInfo::deserialize(data) else {
+        return;
+    };
+
+    let mut buffer = [0u8; 64];
+    let serialized = info.serialize(&mut buffer).unwrap();
+    assert_eq!(NakControlInfo::deserialize(serialized).unwrap(), info);
+});
@@ -0,0 +1,36 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tiny_udt::packet::{ControlPacket, PacketHeader};
+use tiny_udt::packet_ref::{PacketDataRef, PacketRef};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(packet) = PacketRef::parse(data) else {
+        return;
+    };
+
+    match packet {
+        PacketRef::Data(packet) => {
+            let header = packet.header();
+            assert_eq!(PacketHeader::deserialize(data).unwrap(), header);
+            assert_eq!(packet.payload().len(), data.len() - 16);
+
+            let mut buffer = [0u8; 16];
+            assert_eq!(header.serialize(&mut buffer).unwrap(), &data[..16]);
+        }
+        PacketRef::Control(packet) => {
+            let owned = ControlPacket::deserialize(data).unwrap();
+            assert_eq!(owned.data, packet.data().to_owned());
+            assert_eq!(owned.timestamp, packet.timestamp());
+            assert_eq!(owned.id, packet.id());
+
+            if let PacketDataRef::Handshake(info) = packet.data() {
+                let _ = (info.peer_ipv4(), info.peer_ipv6());
+            }
+
+            let mut buffer = [0u8; 80];
+            let serialized = owned.serialize(&mut buffer).unwrap();
+            assert_eq!(ControlPacket::deserialize(serialized).unwrap(), owned);
+        }
+    }
+});
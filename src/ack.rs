@@ -0,0 +1,447 @@
+use std::time::{Duration, Instant};
+
+use crate::error::WindowError;
+use crate::packet::{AckAdditionalInfo, AckControlInfo, PacketData, SpeedAndBandwidth};
+use crate::seq::{seq_cmp, seq_inc};
+use crate::window::AckWindow;
+
+/// Initial RTT estimate
+pub const INITIAL_RTT: Duration = Duration::from_millis(100);
+/// Initial RTT variance estimate
+pub const INITIAL_RTT_VAR: Duration = Duration::from_millis(50);
+
+/// Default number of received packets between light ACKs
+pub const DEFAULT_LIGHT_ACK_INTERVAL: u32 = 64;
+
+/// Default number of sent ACKs waiting for ACK-2
+pub const DEFAULT_ACK_WINDOW_SIZE: usize = 1024;
+
+/// Smoothed round-trip time estimate
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Rtt {
+    rtt: Duration,
+    rtt_var: Duration,
+}
+
+impl Rtt {
+    pub fn rtt(&self) -> Duration {
+        self.rtt
+    }
+
+    pub fn rtt_var(&self) -> Duration {
+        self.rtt_var
+    }
+
+    /// Updates the estimate with a new RTT sample
+    pub fn on_sample(&mut self, sample: Duration) {
+        self.rtt_var = (self.rtt_var * 3 + self.rtt.abs_diff(sample)) / 4;
+        self.rtt = (self.rtt * 7 + sample) / 8;
+    }
+
+    /// Overwrites the estimate with the values reported by the peer
+    pub fn set(&mut self, rtt: Duration, rtt_var: Duration) {
+        self.rtt = rtt;
+        self.rtt_var = rtt_var;
+    }
+}
+
+impl Default for Rtt {
+    fn default() -> Self {
+        Self {
+            rtt: INITIAL_RTT,
+            rtt_var: INITIAL_RTT_VAR,
+        }
+    }
+}
+
+/// Receiver side of the ACK/ACK-2 exchange.
+///
+/// Generates full ACKs and measures RTT when the matching ACK-2 arrives.
+/// Between full ACKs, light ACKs are sent every `light_ack_interval` packets
+/// to keep the sender window open on high-rate links.
+#[derive(Debug)]
+pub struct AckGenerator {
+    window: AckWindow,
+    /// Sequence number of the last sent ACK
+    ack_seq_no: u32,
+    /// Data sequence number carried by the last sent ACK
+    last_ack: u32,
+    /// Time when the last ACK was sent
+    last_ack_time: Option<Instant>,
+    /// Largest data sequence number confirmed by ACK-2
+    last_ack_ack: u32,
+    rtt: Rtt,
+    /// Number of received packets between light ACKs (0 disables light ACKs)
+    light_ack_interval: u32,
+    /// Number of packets received since the last ACK
+    light_ack_count: u32,
+}
+
+impl AckGenerator {
+    /// Creates a generator for the peer initial sequence number
+    pub fn new(isn: u32) -> Self {
+        Self::with_window_size(isn, DEFAULT_ACK_WINDOW_SIZE).expect("valid default window size")
+    }
+
+    /// Creates a generator which keeps up to `window_size` ACKs waiting for ACK-2
+    pub fn with_window_size(isn: u32, window_size: usize) -> Result<Self, WindowError> {
+        Ok(Self {
+            window: AckWindow::new(window_size)?,
+            ack_seq_no: 0,
+            last_ack: isn,
+            last_ack_time: None,
+            last_ack_ack: isn,
+            rtt: Default::default(),
+            light_ack_interval: DEFAULT_LIGHT_ACK_INTERVAL,
+            light_ack_count: 0,
+        })
+    }
+
+    pub fn rtt(&self) -> Rtt {
+        self.rtt
+    }
+
+    pub fn light_ack_interval(&self) -> u32 {
+        self.light_ack_interval
+    }
+
+    /// Sets the number of received packets between light ACKs (0 disables light ACKs)
+    pub fn set_light_ack_interval(&mut self, interval: u32) {
+        self.light_ack_interval = interval;
+    }
+
+    /// Largest data sequence number the sender is known to have acknowledged
+    pub fn last_ack_ack(&self) -> u32 {
+        self.last_ack_ack
+    }
+
+    /// Builds a full ACK for all packets before `received_last_ack` (excluding).
+    ///
+    /// Returns `None` if the sender already confirmed this ACK with ACK-2 or
+    /// if the same ACK was sent less than 2*RTT ago.
+    pub fn on_ack_timer(
+        &mut self,
+        now: Instant,
+        received_last_ack: u32,
+        buffer_size: u32,
+        speed_and_bandwidth: Option<SpeedAndBandwidth>,
+        one_way_delay: Option<u32>,
+    ) -> Option<PacketData> {
+        if received_last_ack == self.last_ack_ack {
+            return None;
+        }
+
+        let order = seq_cmp(received_last_ack, self.last_ack);
+        if order < 0 {
+            return None;
+        }
+        if order == 0 {
+            if let Some(last_ack_time) = self.last_ack_time {
+                if now.saturating_duration_since(last_ack_time) < self.rtt.rtt() * 2 {
+                    return None;
+                }
+            }
+        }
+
+        self.ack_seq_no = seq_inc(self.ack_seq_no);
+        self.last_ack = received_last_ack;
+        self.last_ack_time = Some(now);
+        self.light_ack_count = 0;
+        self.window.store(self.ack_seq_no, received_last_ack, now);
+
+        Some(PacketData::Ack {
+            ack_seq_no: self.ack_seq_no,
+            info: AckControlInfo {
+                received_last_ack,
+                info: Some(AckAdditionalInfo {
+                    rtt: as_micros(self.rtt.rtt()),
+                    rtt_var: as_micros(self.rtt.rtt_var()),
+                    buffer_size,
+                    speed_and_bandwidth,
+                    one_way_delay,
+                }),
+            },
+        })
+    }
+
+    /// Must be called on every received data packet.
+    ///
+    /// Returns a light ACK (without ACK number and additional info) every
+    /// `light_ack_interval` packets unless everything is already confirmed by ACK-2.
+    pub fn on_packet_arrival(&mut self, received_last_ack: u32) -> Option<PacketData> {
+        if self.light_ack_interval == 0 {
+            return None;
+        }
+
+        self.light_ack_count += 1;
+        if self.light_ack_count < self.light_ack_interval {
+            return None;
+        }
+        self.light_ack_count = 0;
+
+        if received_last_ack == self.last_ack_ack {
+            return None;
+        }
+
+        Some(PacketData::Ack {
+            ack_seq_no: 0,
+            info: AckControlInfo {
+                received_last_ack,
+                info: None,
+            },
+        })
+    }
+
+    /// Handles ACK-2 received at `now` and returns the new RTT sample if the ACK is known
+    pub fn on_ack2(&mut self, now: Instant, ack_seq_no: u32) -> Option<Duration> {
+        let ack = self.window.acknowledge(ack_seq_no, now)?;
+
+        if seq_cmp(ack.data_seq_no, self.last_ack_ack) > 0 {
+            self.last_ack_ack = ack.data_seq_no;
+        }
+        self.rtt.on_sample(ack.rtt);
+
+        Some(ack.rtt)
+    }
+}
+
+/// Sender side of the ACK/ACK-2 exchange
+#[derive(Debug)]
+pub struct AckHandler {
+    /// Largest data sequence number acknowledged by the receiver
+    last_ack: u32,
+    rtt: Rtt,
+}
+
+impl AckHandler {
+    /// Creates a handler for the local initial sequence number
+    pub fn new(isn: u32) -> Self {
+        Self {
+            last_ack: isn,
+            rtt: Default::default(),
+        }
+    }
+
+    /// All packets before this sequence number (excluding) are acknowledged
+    pub fn last_ack(&self) -> u32 {
+        self.last_ack
+    }
+
+    pub fn rtt(&self) -> Rtt {
+        self.rtt
+    }
+
+    /// Handles ACK and returns the ACK-2 reply which must be sent immediately.
+    ///
+    /// Light ACKs only advance the acknowledged sequence number and are not confirmed.
+    pub fn on_ack(&mut self, ack_seq_no: u32, info: &AckControlInfo) -> Option<PacketData> {
+        if seq_cmp(info.received_last_ack, self.last_ack) > 0 {
+            self.last_ack = info.received_last_ack;
+        }
+
+        let info = info.info.as_ref()?;
+        self.rtt.set(
+            Duration::from_micros(info.rtt as u64),
+            Duration::from_micros(info.rtt_var as u64),
+        );
+
+        Some(PacketData::Ack2 { ack_seq_no })
+    }
+}
+
+fn as_micros(duration: Duration) -> u32 {
+    duration.as_micros().min(u32::MAX as u128) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::ControlPacket;
+    use crate::transport::{MemoryTransport, Transport};
+
+    fn send(transport: &MemoryTransport, data: PacketData) {
+        let packet = ControlPacket {
+            timestamp: 0,
+            id: 1,
+            data,
+        };
+        let mut buffer = [0; 64];
+        let buffer = packet.serialize(&mut buffer).unwrap();
+        transport.send_to(buffer, &transport.peer_addr()).unwrap();
+    }
+
+    fn recv(transport: &MemoryTransport) -> PacketData {
+        let mut buffer = [0; 64];
+        let (len, _) = transport.recv_from(&mut buffer).unwrap();
+        ControlPacket::deserialize(&buffer[..len]).unwrap().data
+    }
+
+    #[test]
+    fn ack_exchange_measures_rtt() {
+        let (receiver, sender) = MemoryTransport::pair();
+        let mut generator = AckGenerator::new(100);
+        let mut handler = AckHandler::new(100);
+        let start = Instant::now();
+
+        // Nothing received yet
+        assert!(generator
+            .on_ack_timer(start, 100, 8192, None, None)
+            .is_none());
+
+        let ack = generator
+            .on_ack_timer(start, 110, 8192, None, None)
+            .unwrap();
+        send(&receiver, ack);
+
+        // Same ACK within 2*RTT is suppressed
+        assert!(generator
+            .on_ack_timer(start, 110, 8192, None, None)
+            .is_none());
+
+        let PacketData::Ack { ack_seq_no, info } = recv(&sender) else {
+            panic!("ACK expected");
+        };
+        assert_eq!(info.received_last_ack, 110);
+        assert_eq!(info.info.unwrap().rtt, as_micros(INITIAL_RTT));
+
+        send(&sender, handler.on_ack(ack_seq_no, &info).unwrap());
+        assert_eq!(handler.last_ack(), 110);
+
+        let PacketData::Ack2 { ack_seq_no } = recv(&receiver) else {
+            panic!("ACK-2 expected");
+        };
+        let sample = generator
+            .on_ack2(start + Duration::from_millis(20), ack_seq_no)
+            .unwrap();
+        assert_eq!(sample, Duration::from_millis(20));
+        assert_eq!(generator.rtt().rtt(), Duration::from_millis(90));
+        assert_eq!(generator.last_ack_ack(), 110);
+
+        // Sender confirmed the ACK, no need to repeat it even after 2*RTT
+        let later = start + INITIAL_RTT * 2;
+        assert!(generator
+            .on_ack_timer(later, 110, 8192, None, None)
+            .is_none());
+
+        // Unknown ACK-2 is ignored
+        assert!(generator.on_ack2(later, ack_seq_no).is_none());
+
+        let Some(PacketData::Ack { ack_seq_no, info }) = generator.on_ack_timer(
+            later,
+            120,
+            8192,
+            Some(SpeedAndBandwidth {
+                speed: 1000,
+                bandwidth: 2000,
+                bytes: None,
+            }),
+            None,
+        ) else {
+            panic!("ACK expected");
+        };
+        assert_eq!(ack_seq_no, 2);
+        let additional = info.info.unwrap();
+        assert_eq!(additional.rtt, as_micros(generator.rtt().rtt()));
+        assert_eq!(additional.rtt_var, as_micros(generator.rtt().rtt_var()));
+
+        handler.on_ack(ack_seq_no, &info);
+        assert_eq!(
+            handler.rtt().rtt(),
+            Duration::from_micros(additional.rtt as u64)
+        );
+    }
+
+    #[test]
+    fn ack_carries_one_way_delay() {
+        use crate::packet::{ACK_DELAY_SIZE, ACK_MEDIUM_SIZE, HEADER_SIZE};
+        use crate::window::PacketTimeWindow;
+
+        let (receiver, sender) = MemoryTransport::pair();
+        let mut generator = AckGenerator::new(0);
+        let mut window = PacketTimeWindow::new(16, 16).unwrap();
+        window.on_packet_timestamp(1_000, 5_000);
+
+        let ack = generator
+            .on_ack_timer(Instant::now(), 10, 8192, None, window.one_way_delay())
+            .unwrap();
+        send(&receiver, ack);
+
+        let mut buffer = [0; 64];
+        let (len, _) = sender.recv_from(&mut buffer).unwrap();
+        assert_eq!(len, HEADER_SIZE + ACK_MEDIUM_SIZE + ACK_DELAY_SIZE);
+        let PacketData::Ack { info, .. } = ControlPacket::deserialize(&buffer[..len]).unwrap().data
+        else {
+            panic!("ACK expected");
+        };
+        assert_eq!(info.info.unwrap().one_way_delay, Some(4_000));
+    }
+
+    #[test]
+    fn unconfirmed_ack_is_repeated_after_two_rtt() {
+        let mut generator = AckGenerator::new(0);
+        let start = Instant::now();
+
+        assert!(generator.on_ack_timer(start, 5, 0, None, None).is_some());
+        assert!(generator
+            .on_ack_timer(start + INITIAL_RTT, 5, 0, None, None)
+            .is_none());
+        assert!(generator
+            .on_ack_timer(start + INITIAL_RTT * 2, 5, 0, None, None)
+            .is_some());
+
+        // Stale ACK is never sent
+        assert!(generator
+            .on_ack_timer(start + INITIAL_RTT * 4, 4, 0, None, None)
+            .is_none());
+    }
+
+    #[test]
+    fn light_acks_advance_sender_window() {
+        let (receiver, sender) = MemoryTransport::pair();
+        let mut generator = AckGenerator::new(0);
+        let mut handler = AckHandler::new(0);
+        generator.set_light_ack_interval(4);
+
+        let mut light_acks = 0;
+        for seq_no in 0..10 {
+            if let Some(ack) = generator.on_packet_arrival(seq_no + 1) {
+                send(&receiver, ack);
+                light_acks += 1;
+            }
+        }
+        assert_eq!(light_acks, 2);
+
+        for expected in [4, 8] {
+            let PacketData::Ack { ack_seq_no, info } = recv(&sender) else {
+                panic!("ACK expected");
+            };
+            assert!(info.info.is_none());
+            assert!(handler.on_ack(ack_seq_no, &info).is_none());
+            assert_eq!(handler.last_ack(), expected);
+        }
+        assert_eq!(handler.rtt(), Rtt::default());
+
+        // Full ACK restarts the count
+        assert!(generator
+            .on_ack_timer(Instant::now(), 10, 8192, None, None)
+            .is_some());
+        for seq_no in 10..13 {
+            assert!(generator.on_packet_arrival(seq_no + 1).is_none());
+        }
+
+        // Stale light ACK doesn't move the window back
+        handler.on_ack(
+            0,
+            &AckControlInfo {
+                received_last_ack: 2,
+                info: None,
+            },
+        );
+        assert_eq!(handler.last_ack(), 8);
+
+        generator.set_light_ack_interval(0);
+        for seq_no in 13..100 {
+            assert!(generator.on_packet_arrival(seq_no + 1).is_none());
+        }
+    }
+}
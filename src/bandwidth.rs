@@ -0,0 +1,412 @@
+use std::time::{Duration, Instant};
+
+use crate::error::EstimatorError;
+use crate::packet::HEADER_SIZE;
+use crate::window::{
+    PacketSample, PacketTimeWindow, Rate, DEFAULT_ARRIVAL_WINDOW_SIZE, DEFAULT_PROBE_WINDOW_SIZE,
+};
+
+/// Link capacity estimator.
+///
+/// Estimators are fed with packet pair probes (receiver side) and with delivery rate
+/// samples (sender side, from acknowledged data) and ignore samples they don't use.
+pub trait BandwidthEstimator {
+    /// Records the arrival gap of a probe packet pair and the payload size of the second packet
+    fn on_probe(&mut self, _now: Instant, _interval: Duration, _size: usize) {}
+
+    /// Records the amount of data delivered to the peer over a time interval
+    fn on_delivery(&mut self, _now: Instant, _sample: DeliverySample) {}
+
+    /// Current estimate, zero if unknown
+    fn bandwidth(&self) -> Rate;
+}
+
+/// Data delivered to the peer over a time interval
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct DeliverySample {
+    /// Number of delivered packets
+    pub packets: u64,
+    /// Number of delivered bytes (including headers)
+    pub bytes: u64,
+    pub interval: Duration,
+}
+
+impl DeliverySample {
+    pub fn rate(&self) -> Option<Rate> {
+        rate(self.packets, self.bytes, self.interval)
+    }
+}
+
+/// Estimator selection for a connection
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum BandwidthEstimatorKind {
+    /// Median filtered packet pair probes (UDT default)
+    #[default]
+    MedianProbe,
+    /// Exponentially weighted moving average of packet pair probes
+    Ewma {
+        /// Weight of a new sample in `(0, 1]`
+        gain: f64,
+    },
+    /// Maximum delivery rate over a sliding time window (as in BBR)
+    WindowedMax {
+        /// Usually about 10 RTTs
+        window: Duration,
+    },
+}
+
+impl BandwidthEstimatorKind {
+    pub fn build(&self) -> Result<Box<dyn BandwidthEstimator + Send>, EstimatorError> {
+        Ok(match *self {
+            Self::MedianProbe => Box::new(
+                PacketTimeWindow::new(DEFAULT_ARRIVAL_WINDOW_SIZE, DEFAULT_PROBE_WINDOW_SIZE)
+                    .expect("valid default window sizes"),
+            ),
+            Self::Ewma { gain } => Box::new(EwmaEstimator::new(gain)?),
+            Self::WindowedMax { window } => Box::new(WindowedMaxEstimator::new(window)),
+        })
+    }
+}
+
+impl<A, P> BandwidthEstimator for PacketTimeWindow<A, P>
+where
+    A: AsRef<[PacketSample]> + AsMut<[PacketSample]>,
+    P: AsRef<[PacketSample]> + AsMut<[PacketSample]>,
+{
+    fn on_probe(&mut self, _now: Instant, interval: Duration, size: usize) {
+        PacketTimeWindow::on_probe(self, interval, size);
+    }
+
+    fn bandwidth(&self) -> Rate {
+        self.get_bandwidth()
+    }
+}
+
+/// Default weight of a new sample in [`EwmaEstimator`]
+pub const DEFAULT_EWMA_GAIN: f64 = 0.125;
+
+/// Exponentially weighted moving average of packet pair probe rates
+#[derive(Debug, Copy, Clone)]
+pub struct EwmaEstimator {
+    gain: f64,
+    /// Packets and bytes per second, `None` until the first probe
+    rate: Option<(f64, f64)>,
+}
+
+impl EwmaEstimator {
+    /// Creates an estimator giving weight `gain` in `(0, 1]` to a new sample
+    pub fn new(gain: f64) -> Result<Self, EstimatorError> {
+        if !(gain > 0.0 && gain <= 1.0) {
+            return Err(EstimatorError::InvalidGain(gain));
+        }
+        Ok(Self { gain, rate: None })
+    }
+}
+
+impl Default for EwmaEstimator {
+    fn default() -> Self {
+        Self::new(DEFAULT_EWMA_GAIN).expect("valid default gain")
+    }
+}
+
+impl BandwidthEstimator for EwmaEstimator {
+    fn on_probe(&mut self, _now: Instant, interval: Duration, size: usize) {
+        if interval.is_zero() {
+            return;
+        }
+
+        let packets = 1.0 / interval.as_secs_f64();
+        let bytes = (size + HEADER_SIZE) as f64 * packets;
+        self.rate = Some(match self.rate {
+            Some((old_packets, old_bytes)) => (
+                old_packets + self.gain * (packets - old_packets),
+                old_bytes + self.gain * (bytes - old_bytes),
+            ),
+            None => (packets, bytes),
+        });
+    }
+
+    fn bandwidth(&self) -> Rate {
+        let (packets, bytes) = self.rate.unwrap_or_default();
+        Rate {
+            packets_per_sec: packets as u64,
+            bytes_per_sec: bytes as u64,
+        }
+    }
+}
+
+/// Maximum delivery rate over a sliding time window
+#[derive(Debug, Clone)]
+pub struct WindowedMaxEstimator {
+    filter: WindowedMax<Rate>,
+}
+
+impl WindowedMaxEstimator {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            filter: WindowedMax::new(window),
+        }
+    }
+}
+
+impl BandwidthEstimator for WindowedMaxEstimator {
+    fn on_delivery(&mut self, now: Instant, sample: DeliverySample) {
+        if let Some(rate) = sample.rate() {
+            self.filter.update(now, rate);
+        }
+    }
+
+    fn bandwidth(&self) -> Rate {
+        self.filter.get().unwrap_or_default()
+    }
+}
+
+/// Windowed running maximum (Kathleen Nichols' algorithm as used by BBR).
+///
+/// Keeps the best, second best and third best samples from consecutive
+/// sub-windows, so the maximum is updated in O(1).
+#[derive(Debug, Clone)]
+pub struct WindowedMax<T> {
+    window: Duration,
+    samples: Option<[(Instant, T); 3]>,
+}
+
+impl<T: Copy + PartialOrd> WindowedMax<T> {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: None,
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Changes the window length, e.g. when the RTT estimate changes
+    pub fn set_window(&mut self, window: Duration) {
+        self.window = window;
+    }
+
+    /// Maximum over the window ending at the latest update
+    pub fn get(&self) -> Option<T> {
+        self.samples.map(|samples| samples[0].1)
+    }
+
+    pub fn reset(&mut self, now: Instant, value: T) {
+        self.samples = Some([(now, value); 3]);
+    }
+
+    /// Adds a sample and returns the new maximum
+    pub fn update(&mut self, now: Instant, value: T) -> T {
+        let sample = (now, value);
+        let Some(samples) = &mut self.samples else {
+            self.reset(now, value);
+            return value;
+        };
+
+        if value >= samples[0].1 || now.saturating_duration_since(samples[2].0) > self.window {
+            self.reset(now, value);
+            return value;
+        }
+
+        if value >= samples[1].1 {
+            samples[1] = sample;
+            samples[2] = sample;
+        } else if value >= samples[2].1 {
+            samples[2] = sample;
+        }
+
+        // Expire the best samples which have left the window
+        let elapsed = now.saturating_duration_since(samples[0].0);
+        if elapsed > self.window {
+            samples.rotate_left(1);
+            samples[2] = sample;
+            if now.saturating_duration_since(samples[0].0) > self.window {
+                samples.rotate_left(1);
+                samples[2] = sample;
+            }
+        } else if samples[1].0 == samples[0].0 && elapsed > self.window / 4 {
+            // Take a second best sample from the second quarter of the window
+            samples[1] = sample;
+            samples[2] = sample;
+        } else if samples[2].0 == samples[1].0 && elapsed > self.window / 2 {
+            // Take a third best sample from the second half of the window
+            samples[2] = sample;
+        }
+
+        samples[0].1
+    }
+}
+
+fn rate(packets: u64, bytes: u64, interval: Duration) -> Option<Rate> {
+    if interval.is_zero() {
+        return None;
+    }
+
+    let secs = interval.as_secs_f64();
+    Some(Rate {
+        packets_per_sec: (packets as f64 / secs) as u64,
+        bytes_per_sec: (bytes as f64 / secs) as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAYLOAD_SIZE: usize = 1456;
+    /// 10000 packets per second bottleneck
+    const PROBE_GAP: Duration = Duration::from_micros(100);
+
+    /// Probe gaps behind the bottleneck with cross traffic: some pairs get
+    /// a cross traffic packet in between, some get compressed in a later queue
+    fn probe_trace() -> impl Iterator<Item = Duration> {
+        (0..200).map(|i| match i % 10 {
+            3 => PROBE_GAP * 3,
+            7 => PROBE_GAP / 2,
+            9 => PROBE_GAP * 40,
+            _ => PROBE_GAP,
+        })
+    }
+
+    fn assert_close(actual: u64, expected: u64, tolerance: f64) {
+        let error = (actual as f64 - expected as f64).abs() / expected as f64;
+        assert!(
+            error <= tolerance,
+            "{actual} is not within {tolerance} of {expected}"
+        );
+    }
+
+    #[test]
+    fn probe_estimators_follow_bottleneck() {
+        let now = Instant::now();
+        let mut estimators = [
+            BandwidthEstimatorKind::MedianProbe.build().unwrap(),
+            BandwidthEstimatorKind::Ewma {
+                gain: DEFAULT_EWMA_GAIN,
+            }
+            .build()
+            .unwrap(),
+        ];
+
+        for estimator in &mut estimators {
+            for interval in probe_trace() {
+                estimator.on_probe(now, interval, PAYLOAD_SIZE);
+                // Ignored by probe based estimators
+                estimator.on_delivery(
+                    now,
+                    DeliverySample {
+                        packets: 1000,
+                        bytes: 1_500_000,
+                        interval: Duration::from_millis(1),
+                    },
+                );
+            }
+
+            let bandwidth = estimator.bandwidth();
+            assert_close(bandwidth.packets_per_sec, 10_000, 0.3);
+            assert_close(bandwidth.bytes_per_sec, 15_000_000, 0.3);
+        }
+    }
+
+    #[test]
+    fn ewma_smooths_rate_change() {
+        let now = Instant::now();
+        let mut estimator = EwmaEstimator::default();
+        assert_eq!(estimator.bandwidth(), Rate::default());
+
+        for _ in 0..50 {
+            estimator.on_probe(now, PROBE_GAP, PAYLOAD_SIZE);
+        }
+        assert_eq!(estimator.bandwidth().packets_per_sec, 10_000);
+
+        // Capacity halves, the estimate converges within a few dozens of probes
+        estimator.on_probe(now, PROBE_GAP * 2, PAYLOAD_SIZE);
+        assert!(estimator.bandwidth().packets_per_sec > 9000);
+        for _ in 0..50 {
+            estimator.on_probe(now, PROBE_GAP * 2, PAYLOAD_SIZE);
+        }
+        assert_close(estimator.bandwidth().packets_per_sec, 5000, 0.01);
+
+        estimator.on_probe(now, Duration::ZERO, PAYLOAD_SIZE);
+        assert_close(estimator.bandwidth().packets_per_sec, 5000, 0.01);
+    }
+
+    #[test]
+    fn ewma_gain_is_validated() {
+        for gain in [0.0, -0.5, 1.5, f64::NAN] {
+            assert!(matches!(
+                BandwidthEstimatorKind::Ewma { gain }.build(),
+                Err(EstimatorError::InvalidGain(_))
+            ));
+        }
+        assert!(EwmaEstimator::new(1.0).is_ok());
+    }
+
+    #[test]
+    fn windowed_max_tracks_delivery_rate() {
+        let start = Instant::now();
+        let window = Duration::from_millis(500);
+        let mut estimator = BandwidthEstimatorKind::WindowedMax { window }
+            .build()
+            .unwrap();
+        assert_eq!(estimator.bandwidth(), Rate::default());
+
+        // Delivery rate samples every 10 ms: 8000 packets/s with a short 10000 packets/s
+        // burst, then the available capacity drops to 5000 packets/s
+        let mut now = start;
+        for i in 0..200u64 {
+            let packets = match i {
+                0..=19 => 80,
+                20..=22 => 100,
+                23..=99 => 80,
+                _ => 50,
+            };
+            estimator.on_delivery(
+                now,
+                DeliverySample {
+                    packets,
+                    bytes: packets * 1500,
+                    interval: Duration::from_millis(10),
+                },
+            );
+            // Ignored by delivery based estimators
+            estimator.on_probe(now, PROBE_GAP / 10, PAYLOAD_SIZE);
+
+            // Sub-window samples make expiry approximate
+            let expected = match i {
+                0..=19 => Some(8000),
+                // The burst is remembered for the whole window
+                20..=70 => Some(10_000),
+                71..=72 => None,
+                73..=140 => Some(8000),
+                141..=150 => None,
+                _ => Some(5000),
+            };
+            if let Some(expected) = expected {
+                assert_eq!(
+                    estimator.bandwidth().packets_per_sec,
+                    expected,
+                    "sample {i}"
+                );
+            }
+
+            now += Duration::from_millis(10);
+        }
+    }
+
+    #[test]
+    fn windowed_max_expires_old_samples() {
+        let start = Instant::now();
+        let window = Duration::from_secs(1);
+        let mut filter = WindowedMax::new(window);
+        assert_eq!(filter.get(), None);
+
+        assert_eq!(filter.update(start, 10), 10);
+        assert_eq!(filter.update(start + window / 3, 8), 10);
+        assert_eq!(filter.update(start + window * 2 / 3, 6), 10);
+        assert_eq!(filter.update(start + window + window / 10, 1), 8);
+        assert_eq!(filter.update(start + window * 3, 2), 2);
+    }
+}
@@ -0,0 +1,369 @@
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::os::fd::AsRawFd;
+
+use crate::sys::{read_sockaddr, sockaddr_len, write_sockaddr};
+
+/// Preallocated set of packet buffers for batched send and receive
+pub struct PacketBatch {
+    /// Packet buffers, `packet_size` bytes each
+    data: Box<[u8]>,
+    /// Length of each packet
+    lens: Box<[usize]>,
+    /// Address of each packet
+    addrs: Box<[libc::sockaddr_storage]>,
+    /// Syscall scratch space, pointers are rebuilt before each call
+    iovecs: Box<[libc::iovec]>,
+    headers: Box<[libc::mmsghdr]>,
+    /// Maximum packet size
+    packet_size: usize,
+    /// Number of packets in the batch
+    len: usize,
+    /// Error of a partially successful send, returned by the next send
+    send_error: Option<io::Error>,
+}
+
+// SAFETY: raw pointers in `iovecs` and `headers` only refer to the batch's own
+// buffers and are rebuilt right before each syscall
+unsafe impl Send for PacketBatch {}
+
+impl PacketBatch {
+    pub fn new(capacity: usize, packet_size: usize) -> Self {
+        // SAFETY: all these structures are plain C structs for which zeroes are valid
+        let (addr, iovec, header) = unsafe {
+            (
+                std::mem::zeroed::<libc::sockaddr_storage>(),
+                std::mem::zeroed::<libc::iovec>(),
+                std::mem::zeroed::<libc::mmsghdr>(),
+            )
+        };
+
+        Self {
+            data: vec![0; capacity * packet_size].into_boxed_slice(),
+            lens: vec![0; capacity].into_boxed_slice(),
+            addrs: vec![addr; capacity].into_boxed_slice(),
+            iovecs: vec![iovec; capacity].into_boxed_slice(),
+            headers: vec![header; capacity].into_boxed_slice(),
+            packet_size,
+            len: 0,
+            send_error: None,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.lens.len()
+    }
+
+    pub fn packet_size(&self) -> usize {
+        self.packet_size
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == self.capacity()
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.send_error = None;
+    }
+
+    /// Returns the next free buffer to serialize a packet into.
+    ///
+    /// The packet is added to the batch by [`PacketBatch::commit`]
+    pub fn next_buffer(&mut self) -> Option<&mut [u8]> {
+        if self.is_full() {
+            return None;
+        }
+        let offset = self.len * self.packet_size;
+        Some(&mut self.data[offset..offset + self.packet_size])
+    }
+
+    /// Adds the packet written into [`PacketBatch::next_buffer`] to the batch
+    pub fn commit(&mut self, len: usize, addr: SocketAddr) {
+        assert!(!self.is_full() && len <= self.packet_size);
+        self.lens[self.len] = len;
+        write_sockaddr(&addr, &mut self.addrs[self.len]);
+        self.len += 1;
+    }
+
+    /// Copies the packet into the batch.
+    ///
+    /// Returns `false` if the batch is full
+    pub fn push(&mut self, packet: &[u8], addr: SocketAddr) -> bool {
+        match self.next_buffer() {
+            Some(buffer) if packet.len() <= buffer.len() => {
+                buffer[..packet.len()].copy_from_slice(packet);
+                self.commit(packet.len(), addr);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the packet and its address.
+    ///
+    /// Fails if the source address has an unsupported family
+    pub fn get(&self, i: usize) -> Option<io::Result<(&[u8], SocketAddr)>> {
+        if i >= self.len {
+            return None;
+        }
+        let offset = i * self.packet_size;
+        let packet = &self.data[offset..offset + self.lens[i]];
+        Some(match read_sockaddr(&self.addrs[i]) {
+            Some(addr) => Ok((packet, addr)),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported address family",
+            )),
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = io::Result<(&[u8], SocketAddr)>> {
+        (0..self.len).filter_map(move |i| self.get(i))
+    }
+
+    /// Removes the first `count` packets
+    fn remove_front(&mut self, count: usize) {
+        let offset = count * self.packet_size;
+        self.data
+            .copy_within(offset..self.len * self.packet_size, 0);
+        self.lens.copy_within(count..self.len, 0);
+        self.addrs.copy_within(count..self.len, 0);
+        self.len -= count;
+    }
+
+    fn prepare(&mut self, count: usize, sending: bool) {
+        for i in 0..count {
+            let iovec = &mut self.iovecs[i];
+            iovec.iov_base = self.data[i * self.packet_size..].as_mut_ptr() as *mut libc::c_void;
+            iovec.iov_len = if sending {
+                self.lens[i]
+            } else {
+                self.packet_size
+            };
+
+            let header = &mut self.headers[i].msg_hdr;
+            header.msg_name = &mut self.addrs[i] as *mut _ as *mut libc::c_void;
+            header.msg_namelen = if sending {
+                sockaddr_len(&self.addrs[i])
+            } else {
+                std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t
+            };
+            header.msg_iov = iovec;
+            header.msg_iovlen = 1;
+            header.msg_control = std::ptr::null_mut();
+            header.msg_controllen = 0;
+            header.msg_flags = 0;
+            self.headers[i].msg_len = 0;
+        }
+    }
+}
+
+/// Sends all packets from the batch.
+///
+/// Returns the number of packets sent, which is less than the batch size if the
+/// socket is non-blocking and would block, or if sending failed after some packets
+/// were sent. The sent packets are then removed from the batch, so the next call
+/// continues with the rest. An error which occurred after some packets were sent
+/// is returned by the next call (unless the batch is cleared).
+pub fn send_batch(socket: &UdpSocket, batch: &mut PacketBatch) -> io::Result<usize> {
+    if let Some(error) = batch.send_error.take() {
+        return Err(error);
+    }
+
+    let total = batch.len;
+    batch.prepare(total, true);
+
+    let mut sent = 0;
+    while sent < total {
+        // SAFETY: headers are prepared for the first `total` packets
+        let result = unsafe {
+            libc::sendmmsg(
+                socket.as_raw_fd(),
+                batch.headers[sent..].as_mut_ptr(),
+                (total - sent) as libc::c_uint,
+                0,
+            )
+        };
+
+        if result < 0 {
+            let error = io::Error::last_os_error();
+            match error.kind() {
+                io::ErrorKind::Interrupted => continue,
+                io::ErrorKind::WouldBlock if sent > 0 => break,
+                _ if sent > 0 => {
+                    batch.send_error = Some(error);
+                    break;
+                }
+                _ => return Err(error),
+            }
+        }
+        sent += result as usize;
+    }
+
+    if sent < total {
+        batch.remove_front(sent);
+    }
+    Ok(sent)
+}
+
+/// Receives up to `batch.capacity()` packets, replacing the batch contents.
+///
+/// Blocks (unless the socket is non-blocking) until at least one packet arrives
+pub fn recv_batch(socket: &UdpSocket, batch: &mut PacketBatch) -> io::Result<usize> {
+    let capacity = batch.capacity();
+    batch.clear();
+    batch.prepare(capacity, false);
+
+    let received = loop {
+        // SAFETY: headers are prepared for all `capacity` packets
+        let result = unsafe {
+            libc::recvmmsg(
+                socket.as_raw_fd(),
+                batch.headers.as_mut_ptr(),
+                capacity as libc::c_uint,
+                libc::MSG_WAITFORONE,
+                std::ptr::null_mut(),
+            )
+        };
+
+        if result < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(error);
+        }
+        break result as usize;
+    };
+
+    for i in 0..received {
+        batch.lens[i] = batch.headers[i].msg_len as usize;
+    }
+    batch.len = received;
+
+    Ok(received)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loopback(addr: &str) {
+        let sender = UdpSocket::bind(addr).unwrap();
+        let receiver = UdpSocket::bind(addr).unwrap();
+        let receiver_addr = receiver.local_addr().unwrap();
+
+        let mut batch = PacketBatch::new(16, 1500);
+        for i in 0..16u8 {
+            assert!(batch.push(&[i; 100], receiver_addr));
+        }
+        assert!(!batch.push(&[0; 100], receiver_addr));
+        assert_eq!(send_batch(&sender, &mut batch).unwrap(), 16);
+
+        let mut batch = PacketBatch::new(16, 1500);
+        let mut received = 0;
+        while received < 16 {
+            recv_batch(&receiver, &mut batch).unwrap();
+            for result in batch.iter() {
+                let (packet, from) = result.unwrap();
+                assert_eq!(packet, [received as u8; 100]);
+                assert_eq!(from, sender.local_addr().unwrap());
+                received += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn batch_loopback_v4() {
+        loopback("127.0.0.1:0");
+    }
+
+    #[test]
+    fn batch_loopback_v6() {
+        if UdpSocket::bind("[::1]:0").is_ok() {
+            loopback("[::1]:0");
+        }
+    }
+
+    #[test]
+    fn unsupported_address_is_reported() {
+        let mut batch = PacketBatch::new(2, 1500);
+        assert!(batch.push(&[1; 10], "127.0.0.1:1".parse().unwrap()));
+        assert!(batch.push(&[2; 10], "127.0.0.1:2".parse().unwrap()));
+        batch.addrs[1].ss_family = libc::AF_UNIX as libc::sa_family_t;
+
+        let results = batch.iter().collect::<Vec<_>>();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        assert_eq!(
+            results[1].as_ref().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn partial_send_error_is_deferred() {
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        // The second packet is too large for UDP
+        let mut batch = PacketBatch::new(2, 70_000);
+        assert!(batch.push(&[0; 100], receiver.local_addr().unwrap()));
+        assert!(batch.push(&[0; 70_000], receiver.local_addr().unwrap()));
+
+        assert_eq!(send_batch(&sender, &mut batch).unwrap(), 1);
+        let error = send_batch(&sender, &mut batch).unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::EMSGSIZE));
+
+        // Only the failed packet is left
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch.get(0).unwrap().unwrap().0.len(), 70_000);
+
+        // Clearing discards a pending error
+        batch.clear();
+        assert!(batch.push(&[0; 100], receiver.local_addr().unwrap()));
+        assert!(batch.push(&[0; 70_000], receiver.local_addr().unwrap()));
+        assert_eq!(send_batch(&sender, &mut batch).unwrap(), 1);
+        batch.clear();
+        assert!(batch.push(&[1; 100], receiver.local_addr().unwrap()));
+        assert_eq!(send_batch(&sender, &mut batch).unwrap(), 1);
+    }
+
+    #[test]
+    fn retry_after_partial_send_continues() {
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        let receiver_addr = receiver.local_addr().unwrap();
+
+        // The third packet fails, the rest is sent after it is replaced
+        let mut batch = PacketBatch::new(4, 70_000);
+        for i in 0..4u8 {
+            let len = if i == 2 { 70_000 } else { 100 };
+            assert!(batch.push(&vec![i; len], receiver_addr));
+        }
+        assert_eq!(send_batch(&sender, &mut batch).unwrap(), 2);
+        assert!(send_batch(&sender, &mut batch).is_err());
+        assert_eq!(batch.len(), 2);
+
+        batch.lens[0] = 100;
+        assert_eq!(send_batch(&sender, &mut batch).unwrap(), 2);
+
+        let mut buffer = [0; 200];
+        for expected in [0, 1, 2, 3] {
+            let (len, _) = receiver.recv_from(&mut buffer).unwrap();
+            assert_eq!(buffer[..len], [expected; 100]);
+        }
+    }
+}
@@ -0,0 +1,493 @@
+use std::time::{Duration, Instant};
+
+use crate::ack::INITIAL_RTT;
+use crate::bandwidth::WindowedMax;
+use crate::cc::{AckSample, CongestionControl};
+use crate::timer::SYN_INTERVAL;
+use crate::window::Rate;
+
+/// Pacing and window gain during startup (2/ln(2))
+const HIGH_GAIN: f64 = 2.885;
+/// Pacing gains of the probe bandwidth cycle, each phase lasts one min RTT
+const PROBE_BW_GAINS: [f64; 8] = [1.25, 0.75, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0];
+/// Window gain outside of startup and drain
+const PROBE_BW_CWND_GAIN: f64 = 2.0;
+/// The pipe is full when the bandwidth grows less than 25% for `FULL_BW_ROUNDS` rounds
+const FULL_BW_THRESHOLD: f64 = 1.25;
+const FULL_BW_ROUNDS: u32 = 3;
+
+#[derive(Debug, Copy, Clone)]
+pub struct BbrConfig {
+    /// Size of a data packet on the wire (in bytes)
+    pub packet_size: usize,
+    /// Window before the first bandwidth estimate (in packets)
+    pub initial_window: u32,
+    /// Window during probe RTT (in packets)
+    pub min_window: u32,
+    /// Length of the bottleneck bandwidth max filter (in min RTTs)
+    pub bandwidth_window_rounds: u32,
+    /// The min RTT is probed again when it hasn't decreased for this long
+    pub min_rtt_window: Duration,
+    /// Time to keep the window at `min_window` during probe RTT
+    pub probe_rtt_duration: Duration,
+    /// Maximum time between ACKs, the window covers the data delivered meanwhile
+    pub ack_interval: Duration,
+}
+
+impl Default for BbrConfig {
+    fn default() -> Self {
+        Self {
+            packet_size: 1500,
+            initial_window: 16,
+            min_window: 4,
+            bandwidth_window_rounds: 10,
+            min_rtt_window: Duration::from_secs(10),
+            probe_rtt_duration: Duration::from_millis(200),
+            ack_interval: SYN_INTERVAL,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BbrPhase {
+    /// Exponential growth until the bandwidth stops increasing
+    Startup,
+    /// Drains the queue created during startup
+    Drain,
+    /// Cycles the pacing gain around the bottleneck bandwidth
+    ProbeBandwidth,
+    /// Drains the queue to measure the propagation delay
+    ProbeRtt,
+}
+
+/// Model-based congestion control (BBR).
+///
+/// Paces at the bottleneck bandwidth (the maximum delivery rate over the last
+/// rounds) and limits the data in flight to a multiple of the bandwidth-delay
+/// product estimated with the minimum RTT, so the bottleneck queue stays short.
+#[derive(Debug)]
+pub struct Bbr {
+    config: BbrConfig,
+    phase: BbrPhase,
+    pacing_gain: f64,
+    cwnd_gain: f64,
+
+    bandwidth: WindowedMax<Rate>,
+    min_rtt: Option<Duration>,
+    min_rtt_time: Instant,
+
+    /// Start of the current round (one min RTT)
+    round_start: Instant,
+    /// Bandwidth at the last significant growth during startup
+    full_bandwidth: u64,
+    /// Rounds without significant bandwidth growth
+    full_bandwidth_rounds: u32,
+    filled_pipe: bool,
+
+    cycle_index: usize,
+    cycle_start: Instant,
+    /// End of probe RTT, set once the window has drained
+    probe_rtt_end: Option<Instant>,
+    in_flight: u32,
+}
+
+impl Bbr {
+    pub fn new(config: BbrConfig, now: Instant) -> Self {
+        Self {
+            config,
+            phase: BbrPhase::Startup,
+            pacing_gain: HIGH_GAIN,
+            cwnd_gain: HIGH_GAIN,
+            bandwidth: WindowedMax::new(INITIAL_RTT * config.bandwidth_window_rounds),
+            min_rtt: None,
+            min_rtt_time: now,
+            round_start: now,
+            full_bandwidth: 0,
+            full_bandwidth_rounds: 0,
+            filled_pipe: false,
+            cycle_index: 0,
+            cycle_start: now,
+            probe_rtt_end: None,
+            in_flight: 0,
+        }
+    }
+
+    pub fn phase(&self) -> BbrPhase {
+        self.phase
+    }
+
+    /// Estimated bottleneck bandwidth
+    pub fn bandwidth(&self) -> Option<Rate> {
+        self.bandwidth.get()
+    }
+
+    /// Estimated propagation round-trip time
+    pub fn min_rtt(&self) -> Option<Duration> {
+        self.min_rtt
+    }
+
+    /// Bandwidth-delay product (in packets)
+    fn bdp(&self) -> Option<f64> {
+        let bandwidth = self.bandwidth.get()?.bytes_per_sec as f64;
+        let min_rtt = self.min_rtt?.as_secs_f64();
+        Some(bandwidth * min_rtt / self.config.packet_size as f64)
+    }
+
+    /// Packets delivered during one ACK interval (the window opens only when the ACK arrives)
+    fn ack_allowance(&self) -> f64 {
+        self.bandwidth.get().map_or(0.0, |bandwidth| {
+            bandwidth.bytes_per_sec as f64 * self.config.ack_interval.as_secs_f64()
+                / self.config.packet_size as f64
+        })
+    }
+
+    fn rtt(&self) -> Duration {
+        self.min_rtt.unwrap_or(INITIAL_RTT)
+    }
+
+    fn update_min_rtt(&mut self, now: Instant, rtt: Duration) {
+        let expired = now.saturating_duration_since(self.min_rtt_time) > self.config.min_rtt_window;
+        if expired && self.phase != BbrPhase::ProbeRtt {
+            self.enter(BbrPhase::ProbeRtt, now);
+        }
+
+        if rtt.is_zero() {
+            return;
+        }
+        if expired || self.min_rtt.is_none_or(|min_rtt| rtt < min_rtt) {
+            self.min_rtt = Some(rtt);
+            self.min_rtt_time = now;
+        }
+    }
+
+    fn check_full_pipe(&mut self) {
+        let Some(bandwidth) = self.bandwidth.get() else {
+            return;
+        };
+
+        if bandwidth.bytes_per_sec as f64 >= self.full_bandwidth as f64 * FULL_BW_THRESHOLD {
+            self.full_bandwidth = bandwidth.bytes_per_sec;
+            self.full_bandwidth_rounds = 0;
+            return;
+        }
+
+        self.full_bandwidth_rounds += 1;
+        if self.full_bandwidth_rounds >= FULL_BW_ROUNDS {
+            self.filled_pipe = true;
+        }
+    }
+
+    fn enter(&mut self, phase: BbrPhase, now: Instant) {
+        self.phase = phase;
+        match phase {
+            BbrPhase::Startup => {
+                self.pacing_gain = HIGH_GAIN;
+                self.cwnd_gain = HIGH_GAIN;
+            }
+            BbrPhase::Drain => {
+                self.pacing_gain = 1.0 / HIGH_GAIN;
+                self.cwnd_gain = HIGH_GAIN;
+            }
+            BbrPhase::ProbeBandwidth => {
+                // Start with a cruising phase, the queue has just been drained
+                self.cycle_index = 2;
+                self.cycle_start = now;
+                self.pacing_gain = PROBE_BW_GAINS[self.cycle_index];
+                self.cwnd_gain = PROBE_BW_CWND_GAIN;
+            }
+            BbrPhase::ProbeRtt => {
+                self.pacing_gain = 1.0;
+                self.cwnd_gain = 1.0;
+                self.probe_rtt_end = None;
+            }
+        }
+    }
+
+    fn update_phase(&mut self, now: Instant) {
+        let bdp = self.bdp().unwrap_or(self.config.initial_window as f64);
+        let in_flight = self.in_flight as f64;
+
+        match self.phase {
+            BbrPhase::Startup => {
+                if self.filled_pipe {
+                    self.enter(BbrPhase::Drain, now);
+                }
+            }
+            BbrPhase::Drain => {
+                if in_flight <= bdp {
+                    self.enter(BbrPhase::ProbeBandwidth, now);
+                }
+            }
+            BbrPhase::ProbeBandwidth => {
+                let elapsed = now.saturating_duration_since(self.cycle_start) > self.rtt();
+                let gain = PROBE_BW_GAINS[self.cycle_index];
+                let next = if gain > 1.0 {
+                    // Keep probing until the extra data is actually in flight
+                    elapsed && in_flight >= bdp * gain
+                } else if gain < 1.0 {
+                    // Stop draining early once the queue is empty
+                    elapsed || in_flight <= bdp
+                } else {
+                    elapsed
+                };
+
+                if next {
+                    self.cycle_index = (self.cycle_index + 1) % PROBE_BW_GAINS.len();
+                    self.cycle_start = now;
+                    self.pacing_gain = PROBE_BW_GAINS[self.cycle_index];
+                }
+            }
+            BbrPhase::ProbeRtt => {
+                if self.probe_rtt_end.is_none() && self.in_flight <= self.config.min_window {
+                    self.probe_rtt_end = Some(now + self.config.probe_rtt_duration.max(self.rtt()));
+                }
+
+                if matches!(self.probe_rtt_end, Some(end) if now >= end) {
+                    self.min_rtt_time = now;
+                    if self.filled_pipe {
+                        self.enter(BbrPhase::ProbeBandwidth, now);
+                    } else {
+                        self.enter(BbrPhase::Startup, now);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl CongestionControl for Bbr {
+    fn on_ack(&mut self, now: Instant, ack: &AckSample) {
+        self.in_flight = ack.in_flight;
+        // The smoothed RTT stays above the min RTT while there is a queue
+        self.update_min_rtt(now, ack.rtt_sample.unwrap_or(ack.rtt));
+
+        self.bandwidth
+            .set_window(self.rtt() * self.config.bandwidth_window_rounds);
+        if let Some(mut rate) = ack.delivery_rate {
+            // The byte rate is optional in ACK
+            if rate.bytes_per_sec == 0 {
+                rate.bytes_per_sec = rate
+                    .packets_per_sec
+                    .saturating_mul(self.config.packet_size as u64);
+            }
+            if rate.bytes_per_sec > 0 {
+                self.bandwidth.update(now, rate);
+            }
+        }
+
+        if now.saturating_duration_since(self.round_start) >= self.rtt() {
+            self.round_start = now;
+            if !self.filled_pipe {
+                self.check_full_pipe();
+            }
+        }
+
+        self.update_phase(now);
+    }
+
+    fn pacing_interval(&self) -> Duration {
+        let rate = match self.bandwidth.get() {
+            Some(bandwidth) => bandwidth.bytes_per_sec as f64,
+            None => {
+                (self.config.initial_window as usize * self.config.packet_size) as f64
+                    / self.rtt().as_secs_f64()
+            }
+        };
+
+        Duration::from_secs_f64(self.config.packet_size as f64 / (rate * self.pacing_gain))
+    }
+
+    fn window_size(&self) -> u32 {
+        if self.phase == BbrPhase::ProbeRtt {
+            return self.config.min_window;
+        }
+
+        let Some(bdp) = self.bdp() else {
+            return self.config.initial_window;
+        };
+
+        let window = (bdp * self.cwnd_gain + self.ack_allowance()).ceil() as u32;
+        if self.phase == BbrPhase::Startup {
+            window.max(self.config.initial_window)
+        } else {
+            window.max(self.config.min_window)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cc::harness::{transfer, TransferStats, PACKET_SIZE};
+    use crate::packet::HEADER_SIZE;
+    use crate::sim::LinkConfig;
+
+    /// 100 Mbit/s
+    const BANDWIDTH: u64 = 12_500_000;
+
+    fn config() -> BbrConfig {
+        BbrConfig {
+            packet_size: PACKET_SIZE,
+            ..Default::default()
+        }
+    }
+
+    fn link(queue_packets: usize) -> LinkConfig {
+        LinkConfig {
+            latency: Duration::from_millis(20),
+            bandwidth: Some(BANDWIDTH),
+            queue_limit: Some(queue_packets * PACKET_SIZE),
+            ..Default::default()
+        }
+    }
+
+    /// Runs a 12 s transfer and returns the phase changes with the statistics of the last 10 s
+    fn run(link: LinkConfig) -> (Bbr, Vec<(BbrPhase, Duration)>, TransferStats) {
+        // Probe the min RTT a few times during the transfer
+        let config = BbrConfig {
+            min_rtt_window: Duration::from_secs(3),
+            ..config()
+        };
+        let mut bbr = Bbr::new(config, Instant::now());
+        let mut phases = vec![];
+        let stats = transfer(
+            &mut bbr,
+            link,
+            Duration::from_secs(12),
+            Duration::from_secs(2),
+            |bbr, now| {
+                if phases.last().map(|(phase, _)| *phase) != Some(bbr.phase()) {
+                    phases.push((bbr.phase(), now));
+                }
+            },
+        );
+        (bbr, phases, stats)
+    }
+
+    /// Payload capacity of the link
+    fn capacity() -> f64 {
+        BANDWIDTH as f64 * (PACKET_SIZE - HEADER_SIZE) as f64 / PACKET_SIZE as f64
+    }
+
+    #[test]
+    fn fills_pipe_without_filling_deep_buffer() {
+        // 40 ms RTT, BDP is about 340 packets, the buffer holds 1000 packets (118 ms)
+        let (bbr, phases, stats) = run(link(1000));
+
+        let order = phases.iter().map(|(phase, _)| *phase).collect::<Vec<_>>();
+        assert_eq!(
+            order[..3],
+            [BbrPhase::Startup, BbrPhase::Drain, BbrPhase::ProbeBandwidth],
+            "{phases:?}"
+        );
+        assert!(order.contains(&BbrPhase::ProbeRtt), "{phases:?}");
+        assert!(!order[1..].contains(&BbrPhase::Startup), "{phases:?}");
+        // Startup ends within the first second
+        assert!(phases[1].1 < Duration::from_secs(1), "{phases:?}");
+
+        let bandwidth = bbr.bandwidth().unwrap().bytes_per_sec as f64;
+        assert!(
+            (bandwidth - BANDWIDTH as f64).abs() < BANDWIDTH as f64 * 0.05,
+            "{bandwidth}"
+        );
+        let min_rtt = bbr.min_rtt().unwrap();
+        assert!(min_rtt < Duration::from_millis(41), "{min_rtt:?}");
+
+        assert!(stats.throughput > capacity() * 0.9, "{stats:?}");
+        // The queue stays a small fraction of the buffer, even while probing
+        assert!(stats.queue_delay < Duration::from_millis(5), "{stats:?}");
+        assert!(
+            stats.max_queue_delay < Duration::from_millis(20),
+            "{stats:?}"
+        );
+        assert_eq!(stats.dropped, 0);
+    }
+
+    #[test]
+    fn fills_pipe_with_shallow_buffer() {
+        // The buffer holds 64 packets (7.5 ms), a fifth of the BDP
+        let (_, _, stats) = run(link(64));
+
+        assert!(stats.throughput > capacity() * 0.9, "{stats:?}");
+        assert!(stats.queue_delay < Duration::from_millis(2), "{stats:?}");
+    }
+
+    #[test]
+    fn probe_rtt_drains_queue() {
+        let start = Instant::now();
+        let mut bbr = Bbr::new(config(), start);
+        let rate = Rate {
+            packets_per_sec: 1000,
+            bytes_per_sec: 1000 * PACKET_SIZE as u64,
+        };
+        let ack = |in_flight, rtt| AckSample {
+            acked: 10,
+            in_flight,
+            rtt,
+            rtt_sample: Some(rtt),
+            delivery_rate: Some(rate),
+            one_way_delay: None,
+        };
+
+        let mut now = start;
+        for _ in 0..100 {
+            now += Duration::from_millis(10);
+            bbr.on_ack(now, &ack(20, Duration::from_millis(20)));
+        }
+        assert_eq!(bbr.phase(), BbrPhase::ProbeBandwidth);
+        // BDP is 20 packets, 10 more are delivered between ACKs
+        assert_eq!(bbr.window_size(), 50);
+
+        // RTT grows, the min RTT estimate expires after 10 s
+        while bbr.phase() != BbrPhase::ProbeRtt {
+            now += Duration::from_millis(10);
+            bbr.on_ack(now, &ack(30, Duration::from_millis(30)));
+        }
+        assert!(now - start > Duration::from_secs(10));
+        assert_eq!(bbr.window_size(), 4);
+
+        // Waits for the window to drain, then keeps it small for 200 ms
+        now += Duration::from_millis(10);
+        bbr.on_ack(now, &ack(4, Duration::from_millis(30)));
+        let drained = now;
+        while bbr.phase() == BbrPhase::ProbeRtt {
+            now += Duration::from_millis(10);
+            bbr.on_ack(now, &ack(4, Duration::from_millis(30)));
+        }
+        assert!(now - drained >= Duration::from_millis(200));
+        assert_eq!(bbr.phase(), BbrPhase::ProbeBandwidth);
+        assert_eq!(bbr.min_rtt(), Some(Duration::from_millis(30)));
+    }
+
+    #[test]
+    fn packet_rate_is_used_without_byte_rate() {
+        let start = Instant::now();
+        let mut bbr = Bbr::new(config(), start);
+        let ack = AckSample {
+            acked: 10,
+            in_flight: 20,
+            rtt: Duration::from_millis(20),
+            rtt_sample: None,
+            delivery_rate: Some(Rate {
+                packets_per_sec: 1000,
+                bytes_per_sec: 0,
+            }),
+            one_way_delay: None,
+        };
+
+        bbr.on_ack(start + Duration::from_millis(10), &ack);
+        assert_eq!(
+            bbr.bandwidth().unwrap().bytes_per_sec,
+            1000 * PACKET_SIZE as u64
+        );
+
+        // No rate at all is ignored
+        let mut bbr = Bbr::new(config(), start);
+        let ack = AckSample {
+            delivery_rate: Some(Rate::default()),
+            ..ack
+        };
+        bbr.on_ack(start + Duration::from_millis(10), &ack);
+        assert_eq!(bbr.bandwidth(), None);
+    }
+}
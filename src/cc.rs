@@ -0,0 +1,399 @@
+use std::time::{Duration, Instant};
+
+use crate::bbr::{Bbr, BbrConfig};
+use crate::error::CongestionControlError;
+use crate::ledbat::{Ledbat, LedbatConfig};
+use crate::window::Rate;
+
+/// Sender side congestion control algorithm.
+///
+/// The sender must keep at most [`CongestionControl::window_size`] packets unacknowledged
+/// and wait at least [`CongestionControl::pacing_interval`] between data packets.
+pub trait CongestionControl {
+    /// Called for every data packet sent (including retransmissions)
+    fn on_packet_sent(&mut self, _now: Instant, _size: usize) {}
+
+    /// Called for every full ACK
+    fn on_ack(&mut self, now: Instant, ack: &AckSample);
+
+    /// Called when the receiver reports lost packets
+    fn on_loss(&mut self, _now: Instant, _lost: u32) {}
+
+    /// Called when the EXP timer expires with unacknowledged data
+    fn on_timeout(&mut self, _now: Instant) {}
+
+    /// Minimum interval between data packets
+    fn pacing_interval(&self) -> Duration;
+
+    /// Maximum number of unacknowledged packets
+    fn window_size(&self) -> u32;
+}
+
+/// Congestion control selection for a connection
+#[derive(Debug, Copy, Clone)]
+pub enum CongestionControlKind {
+    /// Model-based congestion control
+    Bbr(BbrConfig),
+    /// Scavenger congestion control which yields to other traffic
+    Ledbat(LedbatConfig),
+}
+
+impl Default for CongestionControlKind {
+    fn default() -> Self {
+        Self::Bbr(Default::default())
+    }
+}
+
+impl CongestionControlKind {
+    pub fn build(
+        &self,
+        now: Instant,
+    ) -> Result<Box<dyn CongestionControl + Send>, CongestionControlError> {
+        Ok(match *self {
+            Self::Bbr(config) => Box::new(Bbr::new(config, now)),
+            Self::Ledbat(config) => Box::new(Ledbat::new(config, now)?),
+        })
+    }
+}
+
+/// Information available to the sender when a full ACK arrives
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AckSample {
+    /// Number of packets newly acknowledged by this ACK
+    pub acked: u32,
+    /// Number of unacknowledged packets after this ACK
+    pub in_flight: u32,
+    /// RTT measured with ACK/ACK-2 (reported in the ACK)
+    pub rtt: Duration,
+    /// Unsmoothed RTT of the newest acknowledged packet, from sending it to the arrival
+    /// of this ACK. `None` if unknown, e.g. the packet was retransmitted.
+    pub rtt_sample: Option<Duration>,
+    /// Receiving rate measured by the receiver (reported in the ACK)
+    pub delivery_rate: Option<Rate>,
+    /// One-way delay of the latest data packet (in microseconds, reported in the ACK).
+    /// Includes the clock offset between the peers and wraps around like the timestamps.
+    pub one_way_delay: Option<u32>,
+}
+
+/// Simulated bulk transfer over [`crate::sim`] links for congestion control tests
+#[cfg(test)]
+pub(crate) mod harness {
+    use std::collections::VecDeque;
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::ack::{AckGenerator, AckHandler};
+    use crate::packet::{ControlPacket, NakControlInfo, PacketData, PacketHeader, HEADER_SIZE};
+    use crate::packet_ref::{PacketDataRef, PacketRef};
+    use crate::seq::seq_inc;
+    use crate::sim::{LinkConfig, SimNetwork, SimSocket};
+    use crate::timer::SYN_INTERVAL;
+    use crate::transport::Transport;
+    use crate::window::{PacketTimeWindow, DEFAULT_ARRIVAL_WINDOW_SIZE, DEFAULT_PROBE_WINDOW_SIZE};
+
+    /// Size of every data packet on the wire
+    pub(crate) const PACKET_SIZE: usize = 1472;
+    /// Receiver clock at the start of the transfer (in microseconds), wraps around after 1 s
+    const RECEIVER_CLOCK: u32 = u32::MAX - 1_000_000;
+
+    #[derive(Debug, Default)]
+    pub(crate) struct TransferStats {
+        /// Payload delivered to the receiver after the warm-up (in bytes per second)
+        pub throughput: f64,
+        /// Average queueing delay at the bottleneck after the warm-up
+        pub queue_delay: Duration,
+        /// Maximum queueing delay at the bottleneck after the warm-up
+        pub max_queue_delay: Duration,
+        /// Packets dropped by the bottleneck queue
+        pub dropped: u64,
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 1], port))
+    }
+
+    fn send_control(socket: &SimSocket, to: SocketAddr, timestamp: Duration, data: PacketData) {
+        let packet = ControlPacket {
+            timestamp: timestamp.as_micros() as u32,
+            id: 1,
+            data,
+        };
+        let mut buffer = [0; 64];
+        socket
+            .send_to(packet.serialize(&mut buffer).unwrap(), &to)
+            .unwrap();
+    }
+
+    /// Runs a saturated transfer over `link` (forward direction) for `duration`.
+    ///
+    /// The receiver sends a full ACK every SYN interval with the RTT from the ACK/ACK-2
+    /// exchange and its receiving rate, and a NAK for every sequence gap. The sender adds
+    /// the RTT sample of the newest acknowledged packet. Light ACKs are
+    /// disabled so every ACK reaches the congestion control. Lost packets are not
+    /// retransmitted, the transfer only measures the bottleneck. `observe` is called
+    /// after every ACK.
+    pub(crate) fn transfer<C: CongestionControl>(
+        cc: &mut C,
+        link: LinkConfig,
+        duration: Duration,
+        warm_up: Duration,
+        mut observe: impl FnMut(&C, Duration),
+    ) -> TransferStats {
+        let network = SimNetwork::new(1);
+        network.set_link(addr(1), addr(2), link);
+        network.set_link(
+            addr(2),
+            addr(1),
+            LinkConfig {
+                latency: link.latency,
+                ..Default::default()
+            },
+        );
+        let sender = network.bind(addr(1)).unwrap();
+        let receiver = network.bind(addr(2)).unwrap();
+
+        let mut generator = AckGenerator::new(0);
+        generator.set_light_ack_interval(0);
+        let mut state = Transfer {
+            handler: AckHandler::new(0),
+            next_seq_no: 0,
+            last_send_time: None,
+            send_times: VecDeque::new(),
+            generator,
+            time_window: PacketTimeWindow::new(
+                DEFAULT_ARRIVAL_WINDOW_SIZE,
+                DEFAULT_PROBE_WINDOW_SIZE,
+            )
+            .unwrap(),
+            expected_seq_no: 0,
+            next_ack_time: SYN_INTERVAL,
+            received_bytes: 0,
+            queue_delay_sum: Duration::ZERO,
+            queue_delay_count: 0,
+            max_queue_delay: Duration::ZERO,
+        };
+
+        let serialization = match link.bandwidth.filter(|&bandwidth| bandwidth > 0) {
+            Some(bandwidth) => Duration::from_secs_f64(PACKET_SIZE as f64 / bandwidth as f64),
+            None => Duration::ZERO,
+        };
+        let base_delay = link.latency + serialization;
+
+        while network.now() < duration {
+            let now = network.now();
+            let measuring = now >= warm_up;
+
+            state.receive_data(&receiver, &network, base_delay, measuring);
+            if now >= state.next_ack_time {
+                state.send_ack(&receiver, &network);
+                state.next_ack_time += SYN_INTERVAL;
+            }
+            if state.receive_control(&sender, cc, &network) {
+                observe(cc, now);
+            }
+            state.send_data(&sender, cc, &network);
+
+            // Jump to the next event
+            let mut next = state.next_ack_time;
+            if let Some(arrival) = network.next_arrival() {
+                next = next.min(arrival);
+            }
+            if state.in_flight() < cc.window_size() {
+                next = next.min(state.next_send_time(cc));
+            }
+            network.advance(
+                next.saturating_sub(network.now())
+                    .max(Duration::from_micros(1)),
+            );
+        }
+
+        let measured = (duration - warm_up).as_secs_f64();
+        TransferStats {
+            throughput: state.received_bytes as f64 / measured,
+            queue_delay: state
+                .queue_delay_sum
+                .checked_div(state.queue_delay_count)
+                .unwrap_or_default(),
+            max_queue_delay: state.max_queue_delay,
+            dropped: network.link_stats(addr(1), addr(2)).queue_dropped,
+        }
+    }
+
+    struct Transfer {
+        // Sender
+        handler: AckHandler,
+        next_seq_no: u32,
+        last_send_time: Option<Duration>,
+        /// Send times of the unacknowledged packets
+        send_times: VecDeque<Duration>,
+
+        // Receiver
+        generator: AckGenerator,
+        time_window: PacketTimeWindow,
+        expected_seq_no: u32,
+        next_ack_time: Duration,
+
+        // Measurements
+        received_bytes: u64,
+        queue_delay_sum: Duration,
+        queue_delay_count: u32,
+        max_queue_delay: Duration,
+    }
+
+    impl Transfer {
+        fn in_flight(&self) -> u32 {
+            self.next_seq_no - self.handler.last_ack()
+        }
+
+        /// Pacing follows the current interval, so a rate update applies to the next packet
+        fn next_send_time<C: CongestionControl>(&self, cc: &C) -> Duration {
+            self.last_send_time
+                .map_or(Duration::ZERO, |time| time + cc.pacing_interval())
+        }
+
+        fn send_data<C: CongestionControl>(
+            &mut self,
+            sender: &SimSocket,
+            cc: &mut C,
+            network: &SimNetwork,
+        ) {
+            let now = network.now();
+            let mut packet = [0; PACKET_SIZE];
+
+            while now >= self.next_send_time(cc) && self.in_flight() < cc.window_size() {
+                let header = PacketHeader {
+                    seq_no: self.next_seq_no,
+                    msg_no: 0,
+                    timestamp: now.as_micros() as u32,
+                    id: 2,
+                };
+                header.serialize(&mut packet).unwrap();
+                sender.send_to(&packet, &addr(2)).unwrap();
+                cc.on_packet_sent(network.instant(), PACKET_SIZE);
+
+                self.next_seq_no = seq_inc(self.next_seq_no);
+                self.last_send_time = Some(now);
+                self.send_times.push_back(now);
+            }
+        }
+
+        /// Handles data packets and ACK-2 on the receiver
+        fn receive_data(
+            &mut self,
+            receiver: &SimSocket,
+            network: &SimNetwork,
+            base_delay: Duration,
+            measuring: bool,
+        ) {
+            let now = network.now();
+            let mut buffer = [0; 1500];
+
+            while let Ok((len, _)) = receiver.recv_from(&mut buffer) {
+                let packet = match PacketRef::parse(&buffer[..len]).unwrap() {
+                    PacketRef::Data(packet) => packet,
+                    PacketRef::Control(packet) => {
+                        let PacketDataRef::Ack2 { ack_seq_no } = packet.data() else {
+                            panic!("ACK-2 expected");
+                        };
+                        self.generator.on_ack2(network.instant(), ack_seq_no);
+                        continue;
+                    }
+                };
+
+                if packet.seq_no() > self.expected_seq_no {
+                    let loss = NakControlInfo::Multiple {
+                        loss_data: [self.expected_seq_no | 1 << 31, packet.seq_no() - 1],
+                    };
+                    send_control(receiver, addr(1), now, PacketData::Nak(loss));
+                }
+                self.expected_seq_no = self.expected_seq_no.max(packet.seq_no() + 1);
+                self.time_window
+                    .on_packet_arrival(network.instant(), packet.payload().len());
+                let clock = RECEIVER_CLOCK.wrapping_add(now.as_micros() as u32);
+                self.time_window
+                    .on_packet_timestamp(packet.timestamp(), clock);
+
+                if measuring {
+                    let sent = Duration::from_micros(packet.timestamp() as u64);
+                    let queue_delay = (now - sent).saturating_sub(base_delay);
+                    self.received_bytes += (len - HEADER_SIZE) as u64;
+                    self.queue_delay_sum += queue_delay;
+                    self.queue_delay_count += 1;
+                    self.max_queue_delay = self.max_queue_delay.max(queue_delay);
+                }
+            }
+        }
+
+        fn send_ack(&mut self, receiver: &SimSocket, network: &SimNetwork) {
+            let ack = self.generator.on_ack_timer(
+                network.instant(),
+                self.expected_seq_no,
+                u32::MAX,
+                Some(self.time_window.speed_and_bandwidth()),
+                self.time_window.one_way_delay(),
+            );
+            if let Some(ack) = ack {
+                send_control(receiver, addr(1), network.now(), ack);
+            }
+        }
+
+        /// Handles ACK and NAK on the sender, returns `true` if an ACK was processed
+        fn receive_control<C: CongestionControl>(
+            &mut self,
+            sender: &SimSocket,
+            cc: &mut C,
+            network: &SimNetwork,
+        ) -> bool {
+            let mut acked = false;
+            let mut buffer = [0; 64];
+
+            while let Ok((len, _)) = sender.recv_from(&mut buffer) {
+                let PacketRef::Control(packet) = PacketRef::parse(&buffer[..len]).unwrap() else {
+                    panic!("control packet expected");
+                };
+
+                match packet.data() {
+                    PacketDataRef::Ack { ack_seq_no, info } => {
+                        let last_ack = self.handler.last_ack();
+                        let info = info.to_owned();
+                        if let Some(ack2) = self.handler.on_ack(ack_seq_no, &info) {
+                            send_control(sender, addr(2), network.now(), ack2);
+                        }
+                        if info.received_last_ack <= last_ack {
+                            continue;
+                        }
+
+                        // Lost packets are not retransmitted, so every send time is unique
+                        let newly_acked = info.received_last_ack - last_ack;
+                        let sent = self.send_times.drain(..newly_acked as usize).next_back();
+                        let rates = info.info.and_then(|info| info.speed_and_bandwidth);
+                        let one_way_delay = info.info.and_then(|info| info.one_way_delay);
+                        let sample = AckSample {
+                            acked: newly_acked,
+                            in_flight: self.next_seq_no - info.received_last_ack,
+                            rtt: self.handler.rtt().rtt(),
+                            rtt_sample: sent.map(|sent| network.now() - sent),
+                            delivery_rate: rates.map(|rates| Rate {
+                                packets_per_sec: rates.speed as u64,
+                                bytes_per_sec: rates.bytes.unwrap_or_default().0 as u64,
+                            }),
+                            one_way_delay,
+                        };
+                        cc.on_ack(network.instant(), &sample);
+                        acked = true;
+                    }
+                    PacketDataRef::Nak(info) => {
+                        let NakControlInfo::Multiple { loss_data } = info.to_owned() else {
+                            panic!("loss range expected");
+                        };
+                        let lost = loss_data[1] - (loss_data[0] & !(1 << 31)) + 1;
+                        cc.on_loss(network.instant(), lost);
+                    }
+                    _ => panic!("unexpected control packet"),
+                }
+            }
+
+            acked
+        }
+    }
+}
@@ -0,0 +1,901 @@
+use std::collections::VecDeque;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::ack::{AckGenerator, AckHandler};
+use crate::cc::{AckSample, CongestionControl, CongestionControlKind};
+use crate::error::{CongestionControlError, ConnectionError};
+use crate::packet::{
+    AckControlInfo, ControlPacket, MessageBoundary, NakControlInfo, PacketData, PacketHeader,
+    HEADER_SIZE,
+};
+use crate::packet_ref::{PacketDataRef, PacketRef};
+use crate::pool::{BufferPool, PacketBuffer, ReceiveBuffer, SendBuffer};
+use crate::seq::{msg_inc, seq_add, seq_cmp, seq_inc, seq_offset, MAX_SEQ_NO};
+use crate::state::{ConnectionState, ReadStatus, DEFAULT_LINGER};
+use crate::timer::{ExpAction, ExpTimer, ExpTimerConfig, SYN_INTERVAL};
+use crate::window::{
+    PacketTimeWindow, Rate, DEFAULT_ARRIVAL_WINDOW_SIZE, DEFAULT_PROBE_WINDOW_SIZE,
+};
+
+/// Size of the UDP and IPv4 headers included in the MSS
+pub const UDP_IP_HEADER_SIZE: usize = 28;
+
+/// Smallest flow window, keeps probing a receiver which reported a full buffer
+const MIN_FLOW_WINDOW: u32 = 2;
+
+#[derive(Debug, Copy, Clone)]
+pub struct ConnectionConfig {
+    /// Maximum packet size (including UDP/IP headers)
+    pub mss: u32,
+    /// Maximum number of packets in flight, also the receive buffer size (in packets)
+    pub flight_flag_size: u32,
+    /// Maximum number of packets waiting in the send buffer
+    pub send_buffer_size: u32,
+    /// Time to deliver the remaining data after close
+    pub linger: Duration,
+    pub exp_timer: ExpTimerConfig,
+    pub congestion_control: CongestionControlKind,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            mss: 1500,
+            flight_flag_size: 8192,
+            send_buffer_size: 8192,
+            linger: DEFAULT_LINGER,
+            exp_timer: Default::default(),
+            congestion_control: Default::default(),
+        }
+    }
+}
+
+/// Connection parameters agreed on in the handshake
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ConnectionParams {
+    /// Local socket ID
+    pub id: u32,
+    /// Peer socket ID (destination of the sent packets)
+    pub peer_id: u32,
+    /// Initial sequence number of the sent packets
+    pub isn: u32,
+    /// Initial sequence number of the received packets
+    pub peer_isn: u32,
+    /// Negotiated maximum packet size (including UDP/IP headers)
+    pub mss: u32,
+    /// Negotiated flow window size
+    pub flight_flag_size: u32,
+}
+
+/// Established stream connection without I/O.
+///
+/// Received datagrams are passed to [`Connection::handle_datagram`], the packets to send
+/// are taken with [`Connection::poll_transmit`] until it returns `None`, and
+/// [`Connection::handle_timeout`] must be called at [`Connection::timeout`].
+pub struct Connection {
+    params: ConnectionParams,
+    config: ConnectionConfig,
+    /// Origin of the packet timestamps
+    start: Instant,
+    /// Maximum payload of a data packet
+    payload_size: usize,
+    pool: BufferPool,
+    state: ConnectionState,
+    /// Set when the peer stopped responding
+    broken: bool,
+    /// Control packets waiting for transmission
+    control: VecDeque<PacketData>,
+    next_tick: Instant,
+
+    // Sender
+    send_buffer: SendBuffer,
+    /// Send times of the packets in flight, `None` once retransmitted
+    send_times: VecDeque<Option<Instant>>,
+    /// Sequence number of the first packet never sent
+    next_seq_no: u32,
+    msg_no: u32,
+    send_loss: LossList,
+    ack_handler: AckHandler,
+    /// Packets acknowledged since the last full ACK
+    acked: u32,
+    cc: Box<dyn CongestionControl + Send>,
+    flow_window: u32,
+    /// Earliest time of the next data packet
+    next_send_time: Instant,
+    exp_timer: ExpTimer,
+
+    // Receiver
+    receive_buffer: ReceiveBuffer,
+    /// Partially consumed packet and the number of consumed payload bytes
+    reading: Option<(PacketBuffer, usize)>,
+    /// Sequence number after the largest received one
+    next_expected: u32,
+    receive_loss: LossList,
+    last_nak_time: Instant,
+    ack_generator: AckGenerator,
+    time_window: PacketTimeWindow,
+    /// Number of received data packets, saturates once the arrival window is filled
+    arrivals: usize,
+}
+
+impl Connection {
+    /// Creates a connection which allocates packet buffers from `pool`.
+    ///
+    /// The pool buffers must fit a packet of the negotiated MSS (without UDP/IP headers).
+    pub fn new(
+        config: ConnectionConfig,
+        params: ConnectionParams,
+        pool: BufferPool,
+        now: Instant,
+    ) -> Result<Self, CongestionControlError> {
+        let packet_size = params.mss as usize - UDP_IP_HEADER_SIZE;
+        assert!(packet_size > HEADER_SIZE && packet_size <= pool.buffer_size());
+
+        Ok(Self {
+            params,
+            config,
+            start: now,
+            payload_size: packet_size - HEADER_SIZE,
+            pool,
+            state: ConnectionState::Connected,
+            broken: false,
+            control: VecDeque::new(),
+            next_tick: now + SYN_INTERVAL,
+
+            send_buffer: SendBuffer::new(params.isn),
+            send_times: VecDeque::new(),
+            next_seq_no: params.isn,
+            msg_no: 1,
+            send_loss: LossList::default(),
+            ack_handler: AckHandler::new(params.isn),
+            acked: 0,
+            cc: config.congestion_control.build(now)?,
+            flow_window: params.flight_flag_size,
+            next_send_time: now,
+            exp_timer: ExpTimer::new(config.exp_timer, now),
+
+            receive_buffer: ReceiveBuffer::new(params.peer_isn, params.flight_flag_size as usize),
+            reading: None,
+            next_expected: params.peer_isn,
+            receive_loss: LossList::default(),
+            last_nak_time: now,
+            ack_generator: AckGenerator::new(params.peer_isn),
+            time_window: PacketTimeWindow::new(
+                DEFAULT_ARRIVAL_WINDOW_SIZE,
+                DEFAULT_PROBE_WINDOW_SIZE,
+            )
+            .expect("valid default window sizes"),
+            arrivals: 0,
+        })
+    }
+
+    pub fn params(&self) -> &ConnectionParams {
+        &self.params
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    /// Maximum payload of a data packet
+    pub fn payload_size(&self) -> usize {
+        self.payload_size
+    }
+
+    /// Whether a read would not block (data, EOF or an error)
+    pub fn is_readable(&self) -> bool {
+        self.broken
+            || self.reading.is_some()
+            || self.receive_buffer.has_next()
+            || self.state != ConnectionState::Connected
+    }
+
+    /// Whether a write would not block (buffer space or an error)
+    pub fn is_writable(&self) -> bool {
+        self.broken
+            || self.state.check_write().is_err()
+            || self.send_buffer.len() < self.config.send_buffer_size as usize
+    }
+
+    /// Whether the peer stopped responding
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    /// Whether the connection is closed and has nothing left to transmit
+    pub fn is_closed(&self) -> bool {
+        self.state == ConnectionState::Closed && self.control.is_empty()
+    }
+
+    /// Starts the graceful close, see [`ConnectionState::close`]
+    pub fn close(&mut self, now: Instant) -> io::Result<()> {
+        self.state.close(now, self.config.linger)?;
+        Ok(())
+    }
+
+    /// Queues `data` for sending and returns the number of queued bytes
+    pub fn send(&mut self, data: &[u8]) -> io::Result<usize> {
+        let mut sent = 0;
+        self.send_with(data.len(), |space| {
+            let len = space.len().min(data.len() - sent);
+            space[..len].copy_from_slice(&data[sent..sent + len]);
+            sent += len;
+            Ok(len)
+        })
+    }
+
+    /// Queues up to `max` bytes written by `fill` directly into packet buffers.
+    ///
+    /// `fill` is called with the payload space of every new packet and returns the number
+    /// of written bytes, 0 stops early. Returns [`io::ErrorKind::WouldBlock`] if the send
+    /// buffer is full.
+    pub fn send_with<F>(&mut self, max: usize, mut fill: F) -> io::Result<usize>
+    where
+        F: FnMut(&mut [u8]) -> io::Result<usize>,
+    {
+        if self.broken {
+            return Err(ConnectionError::Broken.into());
+        }
+        self.state.check_write()?;
+
+        let mut total = 0;
+        while total < max {
+            if self.send_buffer.len() >= self.config.send_buffer_size as usize {
+                if total == 0 {
+                    return Err(io::ErrorKind::WouldBlock.into());
+                }
+                break;
+            }
+
+            let mut packet = self.pool.acquire();
+            let len = (max - total).min(self.payload_size);
+            let written = match fill(&mut packet.space_mut()[HEADER_SIZE..HEADER_SIZE + len]) {
+                Ok(0) => break,
+                Ok(written) => written.min(len),
+                Err(e) if total == 0 => return Err(e),
+                Err(_) => break,
+            };
+
+            let seq_no = seq_add(self.ack_handler.last_ack(), self.send_buffer.len() as u32);
+            let mut header = PacketHeader {
+                seq_no,
+                msg_no: 0,
+                // Set on every transmission
+                timestamp: 0,
+                id: self.params.peer_id,
+            };
+            header.set_message(self.msg_no, MessageBoundary::Solo, false);
+            self.msg_no = msg_inc(self.msg_no);
+            header
+                .serialize(packet.space_mut())
+                .expect("buffer fits the header");
+            packet.set_len(HEADER_SIZE + written);
+            self.send_buffer.push(packet);
+            total += written;
+        }
+
+        Ok(total)
+    }
+
+    /// Reads received data into `buffer`, returns 0 at the end of the stream
+    pub fn recv(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let mut received = 0;
+        self.recv_with(buffer.len(), |data| {
+            buffer[received..received + data.len()].copy_from_slice(data);
+            received += data.len();
+            Ok(data.len())
+        })
+    }
+
+    /// Passes up to `max` received bytes to `consume` directly from packet buffers.
+    ///
+    /// `consume` returns the number of consumed bytes, the rest is passed again by the next
+    /// call. Returns 0 at the end of the stream and [`io::ErrorKind::WouldBlock`] if no data
+    /// has arrived yet.
+    pub fn recv_with<F>(&mut self, max: usize, mut consume: F) -> io::Result<usize>
+    where
+        F: FnMut(&[u8]) -> io::Result<usize>,
+    {
+        if self.broken {
+            return Err(ConnectionError::Broken.into());
+        }
+        let available = self.reading.is_some() || self.receive_buffer.has_next();
+        match self.state.check_read(available as usize)? {
+            ReadStatus::Ready => {}
+            ReadStatus::Pending => return Err(io::ErrorKind::WouldBlock.into()),
+            ReadStatus::Eof => return Ok(0),
+        }
+
+        let mut total = 0;
+        while total < max {
+            let Some((packet, offset)) = self
+                .reading
+                .take()
+                .or_else(|| Some((self.receive_buffer.pop()?, 0)))
+            else {
+                break;
+            };
+
+            let data = &packet[HEADER_SIZE + offset..];
+            let len = data.len().min(max - total);
+            let consumed = match consume(&data[..len]) {
+                Ok(consumed) => consumed.min(len),
+                Err(e) => {
+                    self.reading = Some((packet, offset));
+                    if total == 0 {
+                        return Err(e);
+                    }
+                    break;
+                }
+            };
+
+            total += consumed;
+            if consumed < data.len() {
+                self.reading = Some((packet, offset + consumed));
+            }
+            if consumed < len {
+                break;
+            }
+        }
+
+        Ok(total)
+    }
+
+    /// Handles a datagram addressed to this connection
+    pub fn handle_datagram(&mut self, now: Instant, datagram: PacketBuffer) {
+        let packet = match PacketRef::parse(&datagram) {
+            Ok(packet) => packet,
+            Err(_) => return,
+        };
+        self.exp_timer.on_response(now);
+
+        let packet = match packet {
+            PacketRef::Data(packet) => {
+                let seq_no = packet.seq_no();
+                let timestamp = packet.timestamp();
+                let size = packet.payload().len();
+                self.on_data(now, seq_no, timestamp, size, datagram);
+                return;
+            }
+            PacketRef::Control(packet) => packet,
+        };
+
+        match packet.data() {
+            PacketDataRef::Ack { ack_seq_no, info } => {
+                self.on_ack(now, ack_seq_no, &info.to_owned())
+            }
+            PacketDataRef::Nak(info) => self.on_nak(now, &info.to_owned()),
+            PacketDataRef::Ack2 { ack_seq_no } => {
+                self.ack_generator.on_ack2(now, ack_seq_no);
+            }
+            PacketDataRef::Shutdown => self.state.on_shutdown(),
+            _ => {}
+        }
+    }
+
+    /// Takes the next packet to send, writes it into `buffer` and returns its size
+    pub fn poll_transmit(&mut self, now: Instant, buffer: &mut [u8]) -> Option<usize> {
+        if let Some(data) = self.control.pop_front() {
+            let packet = ControlPacket {
+                timestamp: self.timestamp(now),
+                id: self.params.peer_id,
+                data,
+            };
+            let len = packet
+                .serialize(buffer)
+                .expect("buffer fits a packet")
+                .len();
+            self.exp_timer.on_packet_sent(now);
+            return Some(len);
+        }
+
+        if !self.can_send_data() || now < self.next_send_time {
+            return None;
+        }
+
+        let offset = match self.next_retransmission() {
+            Some(offset) => {
+                self.send_times[offset] = None;
+                offset
+            }
+            None if self.in_flight() < self.cc.window_size().min(self.flow_window)
+                && self.in_flight() < self.send_buffer.len() as u32 =>
+            {
+                self.send_times.push_back(Some(now));
+                self.next_seq_no = seq_inc(self.next_seq_no);
+                self.send_times.len() - 1
+            }
+            None => return None,
+        };
+
+        let packet = self
+            .send_buffer
+            .get(seq_add(self.ack_handler.last_ack(), offset as u32))?;
+        let len = packet.len();
+        buffer[..len].copy_from_slice(packet);
+        buffer[8..12].copy_from_slice(&self.timestamp(now).to_le_bytes());
+
+        self.cc.on_packet_sent(now, len);
+        // Time lost to coarse timers is made up by sending back to back,
+        // up to one SYN interval behind the schedule
+        let behind = now.checked_sub(SYN_INTERVAL).unwrap_or(now);
+        self.next_send_time = self.next_send_time.max(behind) + self.cc.pacing_interval();
+        self.exp_timer.on_packet_sent(now);
+        Some(len)
+    }
+
+    /// Time of the next [`Connection::handle_timeout`] or paced transmission
+    pub fn timeout(&self) -> Instant {
+        if self.can_send_data()
+            && (!self.send_loss.is_empty() || self.in_flight() < self.send_buffer.len() as u32)
+        {
+            self.next_tick.min(self.next_send_time)
+        } else {
+            self.next_tick
+        }
+    }
+
+    /// Runs the periodic ACK, NAK, EXP and linger timers
+    pub fn handle_timeout(&mut self, now: Instant) {
+        if now < self.next_tick {
+            return;
+        }
+        self.next_tick = now + SYN_INTERVAL;
+
+        if self.state == ConnectionState::Closed {
+            return;
+        }
+
+        let buffer_size = (self.receive_buffer.available() * self.payload_size) as u32;
+        // The rates are reported once the arrival window is filled with measured intervals
+        let rates = (self.arrivals > DEFAULT_ARRIVAL_WINDOW_SIZE)
+            .then(|| self.time_window.speed_and_bandwidth());
+        let ack = self.ack_generator.on_ack_timer(
+            now,
+            self.received_last_ack(),
+            buffer_size,
+            rates,
+            self.time_window.one_way_delay(),
+        );
+        self.control.extend(ack);
+
+        // Report the losses again in case the NAK or the retransmission was lost
+        let rtt = self.ack_generator.rtt();
+        if let Some((first, last)) = self.receive_loss.front() {
+            if now.saturating_duration_since(self.last_nak_time) > rtt.rtt() + rtt.rtt_var() * 4 {
+                self.send_nak(now, first, last);
+            }
+        }
+
+        let rtt = self.ack_handler.rtt();
+        match self
+            .exp_timer
+            .check(now, rtt.rtt(), rtt.rtt_var(), self.in_flight() > 0)
+        {
+            Ok(Some(ExpAction::Retransmit)) => {
+                let last = seq_add(self.next_seq_no, MAX_SEQ_NO);
+                self.send_loss.insert(self.ack_handler.last_ack(), last);
+                self.cc.on_timeout(now);
+            }
+            Ok(Some(ExpAction::KeepAlive)) => self.control.push_back(PacketData::KeepAlive),
+            Ok(None) => {}
+            Err(_) => {
+                self.broken = true;
+                self.state = ConnectionState::Closed;
+                return;
+            }
+        }
+
+        if self.state.poll_linger(now, self.send_buffer.len()) {
+            self.control.push_back(PacketData::Shutdown);
+        }
+    }
+
+    fn on_data(
+        &mut self,
+        now: Instant,
+        seq_no: u32,
+        timestamp: u32,
+        size: usize,
+        packet: PacketBuffer,
+    ) {
+        self.time_window.on_packet_arrival(now, size);
+        self.arrivals = (self.arrivals + 1).min(DEFAULT_ARRIVAL_WINDOW_SIZE + 1);
+        self.time_window
+            .on_packet_timestamp(timestamp, self.timestamp(now));
+
+        if seq_cmp(seq_no, self.next_expected) >= 0 {
+            // Packets beyond the receive buffer are dropped as if they were lost
+            if !self.receive_buffer.insert(seq_no, packet) {
+                return;
+            }
+            if seq_no != self.next_expected {
+                let last = seq_add(seq_no, MAX_SEQ_NO);
+                self.receive_loss.insert(self.next_expected, last);
+                self.send_nak(now, self.next_expected, last);
+            }
+            self.next_expected = seq_inc(seq_no);
+        } else if self.receive_loss.remove(seq_no) {
+            self.receive_buffer.insert(seq_no, packet);
+        }
+
+        let light_ack = self
+            .ack_generator
+            .on_packet_arrival(self.received_last_ack());
+        self.control.extend(light_ack);
+    }
+
+    fn on_ack(&mut self, now: Instant, ack_seq_no: u32, info: &AckControlInfo) {
+        // Acknowledgement of packets never sent
+        if seq_cmp(info.received_last_ack, self.next_seq_no) > 0 {
+            return;
+        }
+
+        let last_ack = self.ack_handler.last_ack();
+        self.control
+            .extend(self.ack_handler.on_ack(ack_seq_no, info));
+
+        let newly_acked = seq_offset(last_ack, self.ack_handler.last_ack()).max(0) as u32;
+        self.send_buffer.acknowledge(self.ack_handler.last_ack());
+        self.send_loss.remove_before(self.ack_handler.last_ack());
+        let drained = (newly_acked as usize).min(self.send_times.len());
+        let sent = self.send_times.drain(..drained).next_back().flatten();
+        self.acked += newly_acked;
+
+        let Some(info) = info.info else {
+            return;
+        };
+        self.flow_window = (info.buffer_size / self.payload_size as u32).max(MIN_FLOW_WINDOW);
+
+        let acked = std::mem::take(&mut self.acked);
+        let sample = AckSample {
+            acked,
+            in_flight: self.in_flight(),
+            rtt: self.ack_handler.rtt().rtt(),
+            rtt_sample: sent.map(|sent| now.saturating_duration_since(sent)),
+            delivery_rate: info.speed_and_bandwidth.map(|rates| Rate {
+                packets_per_sec: rates.speed as u64,
+                bytes_per_sec: rates.bytes.unwrap_or_default().0 as u64,
+            }),
+            one_way_delay: info.one_way_delay,
+        };
+        self.cc.on_ack(now, &sample);
+    }
+
+    fn on_nak(&mut self, now: Instant, info: &NakControlInfo) {
+        let last_ack = self.ack_handler.last_ack();
+        let last_sent = seq_add(self.next_seq_no, MAX_SEQ_NO);
+        let mut lost = 0;
+
+        for (first, last) in info.ranges() {
+            // Only packets in flight can be retransmitted
+            let first = if seq_cmp(first, last_ack) < 0 {
+                last_ack
+            } else {
+                first
+            };
+            let last = if seq_cmp(last, last_sent) > 0 {
+                last_sent
+            } else {
+                last
+            };
+            if seq_cmp(first, last) > 0 {
+                continue;
+            }
+            self.send_loss.insert(first, last);
+            lost += seq_offset(first, last) as u32 + 1;
+        }
+
+        if lost > 0 {
+            self.cc.on_loss(now, lost);
+        }
+    }
+
+    fn send_nak(&mut self, now: Instant, first: u32, last: u32) {
+        self.control
+            .push_back(PacketData::Nak(NakControlInfo::range(first, last)));
+        self.last_nak_time = now;
+    }
+
+    /// All packets before this sequence number (excluding) have been received
+    fn received_last_ack(&self) -> u32 {
+        self.receive_loss
+            .front()
+            .map_or(self.next_expected, |(first, _)| first)
+    }
+
+    fn in_flight(&self) -> u32 {
+        seq_offset(self.ack_handler.last_ack(), self.next_seq_no) as u32
+    }
+
+    fn can_send_data(&self) -> bool {
+        !self.broken
+            && matches!(
+                self.state,
+                ConnectionState::Connected | ConnectionState::Lingering { .. }
+            )
+    }
+
+    /// Offset of the next lost packet from the last acknowledged one
+    fn next_retransmission(&mut self) -> Option<usize> {
+        while let Some(seq_no) = self.send_loss.pop_front() {
+            let offset = seq_offset(self.ack_handler.last_ack(), seq_no);
+            if offset >= 0 && (offset as usize) < self.send_times.len() {
+                return Some(offset as usize);
+            }
+        }
+        None
+    }
+
+    /// Timestamp of the packets sent at `now` (in microseconds, wraps around)
+    fn timestamp(&self, now: Instant) -> u32 {
+        now.saturating_duration_since(self.start).as_micros() as u32
+    }
+}
+
+impl std::fmt::Debug for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Connection")
+            .field("params", &self.params)
+            .field("state", &self.state)
+            .field("broken", &self.broken)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Sorted list of lost sequence numbers stored as inclusive ranges
+#[derive(Debug, Default)]
+pub(crate) struct LossList {
+    ranges: VecDeque<(u32, u32)>,
+}
+
+impl LossList {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// First range of lost sequence numbers
+    pub(crate) fn front(&self) -> Option<(u32, u32)> {
+        self.ranges.front().copied()
+    }
+
+    /// Adds the inclusive range `first..=last`, merging it with overlapping
+    /// and adjacent ranges
+    pub(crate) fn insert(&mut self, mut first: u32, mut last: u32) {
+        let index = self
+            .ranges
+            .iter()
+            .position(|&(_, end)| seq_cmp(seq_inc(end), first) >= 0)
+            .unwrap_or(self.ranges.len());
+
+        while let Some(&(start, end)) = self.ranges.get(index) {
+            if seq_cmp(start, seq_inc(last)) > 0 {
+                break;
+            }
+            if seq_cmp(start, first) < 0 {
+                first = start;
+            }
+            if seq_cmp(end, last) > 0 {
+                last = end;
+            }
+            self.ranges.remove(index);
+        }
+        self.ranges.insert(index, (first, last));
+    }
+
+    /// Removes the sequence number, returns `false` if it wasn't in the list
+    pub(crate) fn remove(&mut self, seq_no: u32) -> bool {
+        let Some(index) = self
+            .ranges
+            .iter()
+            .position(|&(_, end)| seq_cmp(end, seq_no) >= 0)
+        else {
+            return false;
+        };
+
+        let (first, last) = self.ranges[index];
+        if seq_cmp(first, seq_no) > 0 {
+            return false;
+        }
+
+        match (first == seq_no, last == seq_no) {
+            (true, true) => {
+                self.ranges.remove(index);
+            }
+            (true, false) => self.ranges[index].0 = seq_inc(seq_no),
+            (false, true) => self.ranges[index].1 = seq_add(seq_no, MAX_SEQ_NO),
+            (false, false) => {
+                self.ranges[index].1 = seq_add(seq_no, MAX_SEQ_NO);
+                self.ranges.insert(index + 1, (seq_inc(seq_no), last));
+            }
+        }
+        true
+    }
+
+    /// Removes all sequence numbers before `seq_no` (excluding)
+    pub(crate) fn remove_before(&mut self, seq_no: u32) {
+        while let Some((first, last)) = self.front() {
+            if seq_cmp(last, seq_no) < 0 {
+                self.ranges.pop_front();
+            } else {
+                if seq_cmp(first, seq_no) < 0 {
+                    self.ranges[0].0 = seq_no;
+                }
+                break;
+            }
+        }
+    }
+
+    /// Takes the first lost sequence number
+    pub(crate) fn pop_front(&mut self) -> Option<u32> {
+        let (first, last) = self.front()?;
+        if first == last {
+            self.ranges.pop_front();
+        } else {
+            self.ranges[0].0 = seq_inc(first);
+        }
+        Some(first)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loss_list_merges_ranges() {
+        let mut list = LossList::default();
+        list.insert(10, 12);
+        list.insert(20, 20);
+        list.insert(5, 6);
+        assert_eq!(list.ranges, [(5, 6), (10, 12), (20, 20)]);
+
+        // Adjacent and overlapping ranges are merged
+        list.insert(7, 9);
+        assert_eq!(list.ranges, [(5, 12), (20, 20)]);
+        list.insert(11, 25);
+        assert_eq!(list.ranges, [(5, 25)]);
+    }
+
+    #[test]
+    fn loss_list_removes() {
+        let mut list = LossList::default();
+        list.insert(MAX_SEQ_NO - 1, 2);
+
+        assert!(list.remove(0));
+        assert!(!list.remove(0));
+        assert!(!list.remove(3));
+        assert_eq!(list.ranges, [(MAX_SEQ_NO - 1, MAX_SEQ_NO), (1, 2)]);
+
+        list.remove_before(MAX_SEQ_NO);
+        assert_eq!(list.pop_front(), Some(MAX_SEQ_NO));
+        assert_eq!(list.pop_front(), Some(1));
+        assert_eq!(list.front(), Some((2, 2)));
+        list.remove_before(3);
+        assert!(list.is_empty());
+    }
+
+    fn pair(config: ConnectionConfig, now: Instant) -> (Connection, Connection) {
+        let pool = BufferPool::new(config.mss as usize - UDP_IP_HEADER_SIZE, 64);
+        let params = ConnectionParams {
+            id: 1,
+            peer_id: 2,
+            isn: MAX_SEQ_NO - 5,
+            peer_isn: 100,
+            mss: config.mss,
+            flight_flag_size: config.flight_flag_size,
+        };
+        let peer_params = ConnectionParams {
+            id: 2,
+            peer_id: 1,
+            isn: params.peer_isn,
+            peer_isn: params.isn,
+            ..params
+        };
+        (
+            Connection::new(config, params, pool.clone(), now).unwrap(),
+            Connection::new(config, peer_params, pool, now).unwrap(),
+        )
+    }
+
+    /// Moves all pending packets from `from` to `to`, dropping those rejected by `drop`
+    fn deliver(
+        now: Instant,
+        from: &mut Connection,
+        to: &mut Connection,
+        mut drop: impl FnMut(&[u8]) -> bool,
+    ) -> usize {
+        let mut count = 0;
+        loop {
+            let mut packet = from.pool.acquire();
+            let Some(len) = from.poll_transmit(now, packet.space_mut()) else {
+                return count;
+            };
+            packet.set_len(len);
+            count += 1;
+            if !drop(&packet) {
+                to.handle_datagram(now, packet);
+            }
+        }
+    }
+
+    #[test]
+    fn transfer_with_loss() {
+        let mut now = Instant::now();
+        let (mut sender, mut receiver) = pair(Default::default(), now);
+        let data: Vec<u8> = (0..50_000).map(|i| i as u8).collect();
+        assert_eq!(sender.send(&data).unwrap(), data.len());
+
+        let mut received = Vec::new();
+        let mut count = 0;
+        let mut sent = Vec::new();
+        let mut buffer = [0; 4096];
+        while received.len() < data.len() {
+            now += Duration::from_millis(1);
+            sender.handle_timeout(now);
+            receiver.handle_timeout(now);
+
+            // Drop every 7th data packet on its first transmission
+            deliver(now, &mut sender, &mut receiver, |packet| {
+                let PacketRef::Data(packet) = PacketRef::parse(packet).unwrap() else {
+                    return false;
+                };
+                count += 1;
+                let first = !sent.contains(&packet.seq_no());
+                sent.push(packet.seq_no());
+                first && count % 7 == 0
+            });
+            deliver(now, &mut receiver, &mut sender, |_| false);
+
+            match receiver.recv(&mut buffer) {
+                Ok(len) => received.extend_from_slice(&buffer[..len]),
+                Err(e) => assert_eq!(e.kind(), io::ErrorKind::WouldBlock),
+            }
+            assert!(now < sender.start + Duration::from_secs(10));
+        }
+        assert_eq!(received, data);
+    }
+
+    #[test]
+    fn close_after_delivery() {
+        let mut now = Instant::now();
+        let (mut sender, mut receiver) = pair(Default::default(), now);
+        sender.send(b"hello").unwrap();
+        sender.close(now).unwrap();
+        assert_eq!(
+            sender.send(b"again").unwrap_err().kind(),
+            io::ErrorKind::NotConnected
+        );
+
+        let mut buffer = [0; 16];
+        for _ in 0..100 {
+            now += SYN_INTERVAL;
+            sender.handle_timeout(now);
+            receiver.handle_timeout(now);
+            deliver(now, &mut sender, &mut receiver, |_| false);
+            deliver(now, &mut receiver, &mut sender, |_| false);
+        }
+        assert!(sender.is_closed());
+        assert_eq!(receiver.recv(&mut buffer).unwrap(), 5);
+        assert_eq!(&buffer[..5], b"hello");
+        assert_eq!(receiver.recv(&mut buffer).unwrap(), 0);
+    }
+
+    #[test]
+    fn full_send_buffer_would_block() {
+        let config = ConnectionConfig {
+            send_buffer_size: 2,
+            ..Default::default()
+        };
+        let (mut sender, _) = pair(config, Instant::now());
+        let payload = vec![0; sender.payload_size() * 3];
+
+        assert_eq!(sender.send(&payload).unwrap(), sender.payload_size() * 2);
+        assert!(!sender.is_writable());
+        assert_eq!(
+            sender.send(&payload).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+    }
+}
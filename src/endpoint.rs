@@ -0,0 +1,1067 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::File;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::time::{Duration, Instant};
+
+use crate::connection::{Connection, ConnectionConfig, ConnectionParams, UDP_IP_HEADER_SIZE};
+use crate::error::{ConnectionError, ConnectionSetupError};
+use crate::packet::{ControlPacket, HandshakeControlInfo, PacketData, SocketType, HEADER_SIZE};
+use crate::packet_ref::{PacketDataRef, PacketRef};
+use crate::pool::{BufferPool, PacketBuffer};
+use crate::seq::MAX_SEQ_NO;
+use crate::sim::Rng;
+use crate::transport::Transport;
+
+/// UDT socket ID
+pub type SocketId = u32;
+
+/// Handshake request type of a connection request (or the cookie challenge)
+const REQUEST: i32 = 1;
+/// Handshake request type of the response which establishes the connection
+const RESPONSE: i32 = -1;
+/// Handshake request type of a rejected connection request
+const REJECTED: i32 = 1002;
+
+/// Interval between repeated connection requests
+const CONNECT_RETRY_INTERVAL: Duration = Duration::from_millis(250);
+/// Lifetime of a SYN cookie
+const COOKIE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Copy, Clone)]
+pub struct EndpointConfig {
+    /// Configuration of every connection
+    pub connection: ConnectionConfig,
+    /// Time to wait for the handshake response
+    pub connect_timeout: Duration,
+    /// Number of packet buffers allocated upfront
+    pub pool_capacity: usize,
+    /// Seed of the socket IDs, initial sequence numbers and cookies, random if `None`
+    pub seed: Option<u64>,
+}
+
+impl Default for EndpointConfig {
+    fn default() -> Self {
+        Self {
+            connection: Default::default(),
+            connect_timeout: Duration::from_secs(3),
+            pool_capacity: 1024,
+            seed: None,
+        }
+    }
+}
+
+enum Socket<A> {
+    Connecting {
+        addr: A,
+        isn: u32,
+        /// Cookie of the listener challenge
+        cookie: u32,
+        deadline: Instant,
+        next_request: Instant,
+    },
+    Connected {
+        conn: Box<Connection>,
+        addr: A,
+        /// Set by [`Endpoint::close`], the socket is removed once the connection is closed
+        user_closed: bool,
+    },
+    Failed(ConnectionSetupError),
+}
+
+struct Listener {
+    backlog: usize,
+    /// Connections waiting for [`Endpoint::accept`]
+    queue: VecDeque<SocketId>,
+}
+
+/// UDT sockets multiplexed over one transport.
+///
+/// The endpoint does no I/O on its own: [`Endpoint::drive`] receives all pending datagrams,
+/// runs the timers and sends the packets allowed by the congestion control, it must be called
+/// after the transport becomes readable and at [`Endpoint::timeout`]. Sockets are never blocked,
+/// operations which can't proceed return [`io::ErrorKind::WouldBlock`].
+pub struct Endpoint<T: Transport> {
+    transport: T,
+    config: EndpointConfig,
+    pool: BufferPool,
+    /// Origin of the cookie intervals
+    start: Instant,
+    sockets: BTreeMap<SocketId, Socket<T::Addr>>,
+    /// Accepted connections by the peer address and socket ID with the handshake response,
+    /// which is repeated if the response was lost
+    accepted: HashMap<(T::Addr, u32), (SocketId, HandshakeControlInfo)>,
+    listener: Option<Listener>,
+    rng: Rng,
+    secret: u64,
+    /// Buffer of the outgoing packets
+    transmit: Box<[u8]>,
+}
+
+impl<T: Transport> Endpoint<T> {
+    /// Creates an endpoint over `transport` and switches it to non-blocking mode
+    pub fn new(transport: T, config: EndpointConfig) -> io::Result<Self> {
+        let packet_size = (config.connection.mss as usize)
+            .checked_sub(UDP_IP_HEADER_SIZE)
+            .filter(|&size| size > HEADER_SIZE)
+            .ok_or_else(|| invalid_input("MSS is too small"))?;
+        if config.connection.flight_flag_size == 0 || config.connection.send_buffer_size == 0 {
+            return Err(invalid_input("buffer sizes must not be zero"));
+        }
+        config
+            .connection
+            .congestion_control
+            .build(Instant::now())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        transport.set_nonblocking(true)?;
+
+        let seed = config
+            .seed
+            .unwrap_or_else(|| std::collections::hash_map::RandomState::new().hash_one(0));
+        let mut rng = Rng::new(seed);
+        let secret = rng.next_u64();
+
+        Ok(Self {
+            transport,
+            config,
+            pool: BufferPool::new(packet_size, config.pool_capacity),
+            start: Instant::now(),
+            sockets: BTreeMap::new(),
+            accepted: HashMap::new(),
+            listener: None,
+            rng,
+            secret,
+            transmit: vec![0; packet_size].into_boxed_slice(),
+        })
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn local_addr(&self) -> io::Result<T::Addr> {
+        self.transport.local_addr()
+    }
+
+    /// Accepts connection requests, up to `backlog` connections wait for [`Endpoint::accept`]
+    pub fn listen(&mut self, backlog: usize) {
+        match &mut self.listener {
+            Some(listener) => listener.backlog = backlog,
+            None => {
+                self.listener = Some(Listener {
+                    backlog,
+                    queue: VecDeque::new(),
+                })
+            }
+        }
+    }
+
+    /// Takes an established connection, returns [`io::ErrorKind::WouldBlock`] if none is ready
+    pub fn accept(&mut self) -> io::Result<(SocketId, T::Addr)> {
+        let listener = self
+            .listener
+            .as_mut()
+            .ok_or_else(|| invalid_input("endpoint is not listening"))?;
+
+        while let Some(id) = listener.queue.pop_front() {
+            if let Some(Socket::Connected { addr, .. }) = self.sockets.get(&id) {
+                return Ok((id, addr.clone()));
+            }
+        }
+        Err(io::ErrorKind::WouldBlock.into())
+    }
+
+    /// Whether [`Endpoint::accept`] would return a connection
+    pub fn is_acceptable(&self) -> bool {
+        self.listener.as_ref().is_some_and(|listener| {
+            listener
+                .queue
+                .iter()
+                .any(|id| matches!(self.sockets.get(id), Some(Socket::Connected { .. })))
+        })
+    }
+
+    /// Starts connecting to the listener at `addr`.
+    ///
+    /// The socket becomes writable once the connection is established,
+    /// see [`Endpoint::is_connected`].
+    pub fn connect(&mut self, addr: T::Addr, now: Instant) -> io::Result<SocketId> {
+        let id = self.new_socket_id();
+        let isn = self.new_isn();
+        self.sockets.insert(
+            id,
+            Socket::Connecting {
+                addr,
+                isn,
+                cookie: 0,
+                deadline: now + self.config.connect_timeout,
+                next_request: now,
+            },
+        );
+        self.drive_socket(id, now)?;
+        Ok(id)
+    }
+
+    /// Returns `false` while connecting and the setup error if the connection failed
+    pub fn is_connected(&self, id: SocketId) -> io::Result<bool> {
+        match self.socket(id)? {
+            Socket::Connecting { .. } => Ok(false),
+            Socket::Connected { .. } => Ok(true),
+            Socket::Failed(error) => Err((*error).into()),
+        }
+    }
+
+    /// Peer address of the socket
+    pub fn peer_addr(&self, id: SocketId) -> io::Result<T::Addr> {
+        match self.socket(id)? {
+            Socket::Connecting { addr, .. } | Socket::Connected { addr, .. } => Ok(addr.clone()),
+            Socket::Failed(error) => Err((*error).into()),
+        }
+    }
+
+    /// Established connection of the socket
+    pub fn connection(&self, id: SocketId) -> io::Result<&Connection> {
+        match self.socket(id)? {
+            Socket::Connected { conn, .. } => Ok(conn),
+            Socket::Connecting { .. } => Err(io::ErrorKind::NotConnected.into()),
+            Socket::Failed(error) => Err((*error).into()),
+        }
+    }
+
+    /// Mutable access to the established connection of the socket.
+    ///
+    /// Returns [`io::ErrorKind::WouldBlock`] while connecting.
+    pub fn connection_mut(&mut self, id: SocketId) -> io::Result<&mut Connection> {
+        match self.sockets.get_mut(&id) {
+            Some(Socket::Connected {
+                conn,
+                user_closed: false,
+                ..
+            }) => Ok(conn),
+            Some(Socket::Connecting { .. }) => Err(io::ErrorKind::WouldBlock.into()),
+            Some(Socket::Failed(error)) => Err((*error).into()),
+            Some(Socket::Connected { .. }) | None => Err(ConnectionError::NotExist.into()),
+        }
+    }
+
+    /// Queues `data` for sending, see [`Connection::send`]
+    pub fn send(&mut self, id: SocketId, data: &[u8]) -> io::Result<usize> {
+        self.connection_mut(id)?.send(data)
+    }
+
+    /// Reads received data, see [`Connection::recv`]
+    pub fn recv(&mut self, id: SocketId, buffer: &mut [u8]) -> io::Result<usize> {
+        self.connection_mut(id)?.recv(buffer)
+    }
+
+    /// Closes the socket.
+    ///
+    /// An established connection delivers the remaining data first and the socket is removed
+    /// by [`Endpoint::drive`] once the connection is closed.
+    pub fn close(&mut self, id: SocketId, now: Instant) -> io::Result<()> {
+        match self.sockets.get_mut(&id) {
+            Some(Socket::Connected {
+                conn, user_closed, ..
+            }) if !*user_closed => {
+                *user_closed = true;
+                // The connection is already closed if the peer is gone
+                if let Err(e) = conn.close(now) {
+                    if !conn.is_closed() {
+                        return Err(e);
+                    }
+                }
+            }
+            Some(Socket::Connecting { .. } | Socket::Failed(_)) => {
+                self.sockets.remove(&id);
+            }
+            Some(Socket::Connected { .. }) | None => return Err(ConnectionError::NotExist.into()),
+        }
+        self.remove_closed();
+        Ok(())
+    }
+
+    /// Sends `size` bytes of `file` starting at `offset`, blocking until they are queued.
+    ///
+    /// The file is read directly into the packet buffers. Returns the number of sent bytes,
+    /// which is less than `size` if the file ends earlier.
+    pub fn send_file(
+        &mut self,
+        id: SocketId,
+        file: &mut File,
+        offset: u64,
+        size: u64,
+    ) -> io::Result<u64> {
+        file.seek(SeekFrom::Start(offset))?;
+
+        let mut sent = 0;
+        while sent < size {
+            let max = (size - sent).min(usize::MAX as u64) as usize;
+            match self
+                .connection_mut(id)?
+                .send_with(max, |space| file.read(space))
+            {
+                Ok(0) => break,
+                Ok(len) => sent += len as u64,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => self.block()?,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+            self.drive(Instant::now())?;
+        }
+        Ok(sent)
+    }
+
+    /// Receives up to `size` bytes into `file` starting at `offset`, blocking until they
+    /// arrive or the peer closes the connection.
+    ///
+    /// The file is written directly from the packet buffers. Returns the number of received
+    /// bytes.
+    pub fn recv_file(
+        &mut self,
+        id: SocketId,
+        file: &mut File,
+        offset: u64,
+        size: u64,
+    ) -> io::Result<u64> {
+        file.seek(SeekFrom::Start(offset))?;
+
+        let mut received = 0;
+        while received < size {
+            self.drive(Instant::now())?;
+            let max = (size - received).min(usize::MAX as u64) as usize;
+            let result = self.connection_mut(id)?.recv_with(max, |data| {
+                file.write_all(data)?;
+                Ok(data.len())
+            });
+            match result {
+                Ok(0) => break,
+                Ok(len) => received += len as u64,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => self.block()?,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(received)
+    }
+
+    /// Receives all pending datagrams, runs the timers and sends the pending packets
+    pub fn drive(&mut self, now: Instant) -> io::Result<()> {
+        loop {
+            let mut datagram = self.pool.acquire();
+            match datagram.recv_from(&self.transport) {
+                Ok(addr) => self.handle_datagram(now, addr, datagram),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                // ICMP errors of previously sent datagrams
+                Err(e) if e.kind() == io::ErrorKind::ConnectionReset => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let ids: Vec<_> = self.sockets.keys().copied().collect();
+        for id in ids {
+            self.drive_socket(id, now)?;
+        }
+        self.remove_closed();
+        Ok(())
+    }
+
+    /// Time of the next [`Endpoint::drive`] without received datagrams
+    pub fn timeout(&self) -> Option<Instant> {
+        self.sockets
+            .values()
+            .filter_map(|socket| match socket {
+                Socket::Connecting {
+                    deadline,
+                    next_request,
+                    ..
+                } => Some(*deadline.min(next_request)),
+                Socket::Connected { conn, .. } => Some(conn.timeout()),
+                Socket::Failed(_) => None,
+            })
+            .min()
+    }
+
+    /// Blocks until the transport is readable, `deadline` or [`Endpoint::timeout`].
+    ///
+    /// Returns [`io::ErrorKind::Unsupported`] for transports which can't block.
+    pub fn wait(&self, deadline: Option<Instant>) -> io::Result<()> {
+        let now = Instant::now();
+        let deadline = match (deadline, self.timeout()) {
+            (Some(deadline), Some(timeout)) => Some(deadline.min(timeout)),
+            (deadline, timeout) => deadline.or(timeout),
+        };
+        self.transport
+            .wait_readable(deadline.map(|deadline| deadline.saturating_duration_since(now)))?;
+        Ok(())
+    }
+
+    /// Waits for the transport and drives the endpoint
+    fn block(&mut self) -> io::Result<()> {
+        self.wait(None)?;
+        self.drive(Instant::now())
+    }
+
+    /// IDs of the sockets, including those closed but still delivering data
+    pub fn sockets(&self) -> impl Iterator<Item = SocketId> + '_ {
+        self.sockets.keys().copied()
+    }
+
+    fn socket(&self, id: SocketId) -> io::Result<&Socket<T::Addr>> {
+        match self.sockets.get(&id) {
+            Some(Socket::Connected {
+                user_closed: true, ..
+            })
+            | None => Err(ConnectionError::NotExist.into()),
+            Some(socket) => Ok(socket),
+        }
+    }
+
+    fn handle_datagram(&mut self, now: Instant, addr: T::Addr, datagram: PacketBuffer) {
+        let Ok(packet) = PacketRef::parse(&datagram) else {
+            return;
+        };
+
+        if let PacketRef::Control(control) = packet {
+            if let PacketDataRef::Handshake(handshake) = control.data() {
+                let handshake = handshake.to_owned();
+                match control.id() {
+                    0 => self.on_request(now, addr, handshake),
+                    id => self.on_response(now, id, addr, handshake),
+                }
+                return;
+            }
+        }
+
+        if let Some(Socket::Connected {
+            conn,
+            addr: peer_addr,
+            ..
+        }) = self.sockets.get_mut(&packet.id())
+        {
+            if *peer_addr == addr {
+                conn.handle_datagram(now, datagram);
+            }
+        }
+    }
+
+    /// Handles a connection request on the listener
+    fn on_request(&mut self, now: Instant, addr: T::Addr, request: HandshakeControlInfo) {
+        if request.request_type != REQUEST {
+            return;
+        }
+        let mut response = HandshakeControlInfo {
+            isn: 0,
+            request_type: REJECTED,
+            id: 0,
+            ..request
+        };
+
+        if self.listener.is_some() {
+            // Repeat the response if it was lost
+            if let Some((_, response)) = self.accepted.get(&(addr.clone(), request.id)) {
+                let response = *response;
+                self.send_handshake(now, &addr, request.id, response);
+                return;
+            }
+
+            let cookie = self.cookie(now, &addr, 0);
+            if request.cookie != cookie && request.cookie != self.cookie(now, &addr, 1) {
+                response.request_type = REQUEST;
+                response.cookie = cookie;
+                self.send_handshake(now, &addr, request.id, response);
+                return;
+            }
+
+            if let Some(accepted) = self.accept_connection(now, &addr, &request) {
+                response = accepted;
+            }
+        }
+        self.send_handshake(now, &addr, request.id, response);
+    }
+
+    /// Creates the connection for a valid request and returns the handshake response,
+    /// `None` if the backlog is full
+    fn accept_connection(
+        &mut self,
+        now: Instant,
+        addr: &T::Addr,
+        request: &HandshakeControlInfo,
+    ) -> Option<HandshakeControlInfo> {
+        let listener = self.listener.as_ref()?;
+        if listener.queue.len() >= listener.backlog {
+            return None;
+        }
+
+        let config = self.config.connection;
+        let params = ConnectionParams {
+            id: self.new_socket_id(),
+            peer_id: request.id,
+            isn: self.new_isn(),
+            peer_isn: request.isn,
+            mss: request.mss.min(config.mss),
+            flight_flag_size: request.flight_flag_size.min(config.flight_flag_size),
+        };
+        let conn = self.new_connection(params, now)?;
+
+        let response = HandshakeControlInfo {
+            isn: params.isn,
+            mss: params.mss,
+            flight_flag_size: params.flight_flag_size,
+            request_type: RESPONSE,
+            id: params.id,
+            ..*request
+        };
+        self.sockets.insert(
+            params.id,
+            Socket::Connected {
+                conn,
+                addr: addr.clone(),
+                user_closed: false,
+            },
+        );
+        self.accepted
+            .insert((addr.clone(), request.id), (params.id, response));
+        if let Some(listener) = &mut self.listener {
+            listener.queue.push_back(params.id);
+        }
+        Some(response)
+    }
+
+    /// Handles the listener reply to a connection request
+    fn on_response(
+        &mut self,
+        now: Instant,
+        id: SocketId,
+        addr: T::Addr,
+        response: HandshakeControlInfo,
+    ) {
+        let Some(Socket::Connecting {
+            addr: peer_addr,
+            isn,
+            cookie,
+            next_request,
+            ..
+        }) = self.sockets.get_mut(&id)
+        else {
+            return;
+        };
+        if *peer_addr != addr {
+            return;
+        }
+
+        match response.request_type {
+            REQUEST => {
+                *cookie = response.cookie;
+                *next_request = now;
+                let _ = self.drive_socket(id, now);
+            }
+            RESPONSE => {
+                let config = self.config.connection;
+                let params = ConnectionParams {
+                    id,
+                    peer_id: response.id,
+                    isn: *isn,
+                    peer_isn: response.isn,
+                    mss: response.mss.min(config.mss),
+                    flight_flag_size: response.flight_flag_size.min(config.flight_flag_size),
+                };
+                let socket = match self.new_connection(params, now) {
+                    Some(conn) => Socket::Connected {
+                        conn,
+                        addr,
+                        user_closed: false,
+                    },
+                    None => Socket::Failed(ConnectionSetupError::ConnectionRejected),
+                };
+                self.sockets.insert(id, socket);
+            }
+            REJECTED => {
+                self.sockets
+                    .insert(id, Socket::Failed(ConnectionSetupError::ConnectionRejected));
+            }
+            _ => {}
+        }
+    }
+
+    fn new_connection(&self, params: ConnectionParams, now: Instant) -> Option<Box<Connection>> {
+        // The packets must fit the pool buffers
+        if (params.mss as usize) < UDP_IP_HEADER_SIZE + HEADER_SIZE + 1
+            || params.flight_flag_size == 0
+        {
+            return None;
+        }
+        Connection::new(self.config.connection, params, self.pool.clone(), now)
+            .ok()
+            .map(Box::new)
+    }
+
+    /// Runs the socket timers and sends its pending packets
+    fn drive_socket(&mut self, id: SocketId, now: Instant) -> io::Result<()> {
+        let Some(socket) = self.sockets.get_mut(&id) else {
+            return Ok(());
+        };
+
+        match socket {
+            Socket::Connecting {
+                addr,
+                isn,
+                cookie,
+                deadline,
+                next_request,
+            } => {
+                if now >= *deadline {
+                    *socket = Socket::Failed(ConnectionSetupError::ConnectionTimeOut);
+                } else if now >= *next_request {
+                    *next_request = now + CONNECT_RETRY_INTERVAL;
+                    let config = self.config.connection;
+                    let request = HandshakeControlInfo {
+                        socket_type: SocketType::Stream,
+                        isn: *isn,
+                        mss: config.mss,
+                        flight_flag_size: config.flight_flag_size,
+                        request_type: REQUEST,
+                        id,
+                        cookie: *cookie,
+                        ip: [0; 4],
+                    };
+                    let addr = addr.clone();
+                    self.send_handshake(now, &addr, 0, request);
+                }
+            }
+            Socket::Connected { conn, addr, .. } => {
+                conn.handle_timeout(now);
+                while let Some(len) = conn.poll_transmit(now, &mut self.transmit) {
+                    match self.transport.send_to(&self.transmit[..len], addr) {
+                        Ok(_) => {}
+                        // Dropped like on a congested link
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                        Err(e) if e.kind() == io::ErrorKind::ConnectionReset => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            Socket::Failed(_) => {}
+        }
+        Ok(())
+    }
+
+    fn send_handshake(
+        &mut self,
+        now: Instant,
+        addr: &T::Addr,
+        id: SocketId,
+        handshake: HandshakeControlInfo,
+    ) {
+        let packet = ControlPacket {
+            timestamp: now.saturating_duration_since(self.start).as_micros() as u32,
+            id,
+            data: PacketData::Handshake(handshake),
+        };
+        let len = packet
+            .serialize(&mut self.transmit)
+            .expect("buffer fits the handshake")
+            .len();
+        // Lost handshakes are repeated by the connecting side
+        let _ = self.transport.send_to(&self.transmit[..len], addr);
+    }
+
+    /// Removes the sockets closed by the user once their connections are closed
+    fn remove_closed(&mut self) {
+        self.sockets.retain(|_, socket| match socket {
+            Socket::Connected {
+                conn,
+                user_closed: true,
+                ..
+            } => !conn.is_closed(),
+            _ => true,
+        });
+        let sockets = &self.sockets;
+        self.accepted.retain(|_, (id, _)| sockets.contains_key(id));
+    }
+
+    /// SYN cookie of the peer address, valid for the current and the previous interval
+    fn cookie(&self, now: Instant, addr: &T::Addr, intervals_ago: u64) -> u32 {
+        let interval =
+            now.saturating_duration_since(self.start).as_secs() / COOKIE_INTERVAL.as_secs();
+        let mut hasher = DefaultHasher::new();
+        self.secret.hash(&mut hasher);
+        addr.hash(&mut hasher);
+        interval.wrapping_sub(intervals_ago).hash(&mut hasher);
+        hasher.finish() as u32
+    }
+
+    fn new_socket_id(&mut self) -> SocketId {
+        loop {
+            let id = self.rng.next_u64() as u32;
+            if id != 0 && !self.sockets.contains_key(&id) {
+                return id;
+            }
+        }
+    }
+
+    fn new_isn(&mut self) -> u32 {
+        self.rng.next_u64() as u32 & MAX_SEQ_NO
+    }
+}
+
+impl<T: Transport + std::fmt::Debug> std::fmt::Debug for Endpoint<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Endpoint")
+            .field("transport", &self.transport)
+            .field("sockets", &self.sockets.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Registers the transport, so the endpoint is driven by the same `mio::Poll` as the
+/// application sockets.
+///
+/// An event of the endpoint token means datagrams are pending. Readiness of the UDT sockets
+/// follows from their buffers, not from the transport: after an event of the endpoint token
+/// or at [`Endpoint::timeout`] (use it as the poll timeout), call
+/// [`UdtPoll::wait`](crate::poll::UdtPoll::wait) with a zero timeout, which drives the
+/// endpoint and reports the readable and writable sockets. Registering for writable
+/// interest is not needed, sending is paced by the endpoint.
+#[cfg(feature = "mio")]
+impl<T: Transport + mio::event::Source> mio::event::Source for Endpoint<T> {
+    fn register(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        interests: mio::Interest,
+    ) -> io::Result<()> {
+        self.transport.register(registry, token, interests)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        interests: mio::Interest,
+    ) -> io::Result<()> {
+        self.transport.reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &mio::Registry) -> io::Result<()> {
+        self.transport.deregister(registry)
+    }
+}
+
+fn invalid_input(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, UdpSocket};
+
+    use super::*;
+    use crate::sim::{BurstLoss, LinkConfig, LinkStats, SimNetwork};
+
+    fn endpoint() -> Endpoint<UdpSocket> {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        Endpoint::new(socket, Default::default()).unwrap()
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("tiny-udt-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn file_transfer() {
+        let data: Vec<u8> = (0..3_000_000u32).map(|i| (i % 251) as u8).collect();
+        let source_path = temp_path("source");
+        let target_path = temp_path("target");
+        std::fs::write(&source_path, &data).unwrap();
+
+        let mut server = endpoint();
+        server.listen(1);
+        let addr = server.local_addr().unwrap();
+
+        let receiver = std::thread::spawn(move || {
+            let id = loop {
+                match server.accept() {
+                    Ok((id, _)) => break id,
+                    Err(e) => assert_eq!(e.kind(), io::ErrorKind::WouldBlock),
+                }
+                server.wait(None).unwrap();
+                server.drive(Instant::now()).unwrap();
+            };
+
+            // Leaves the first 10 bytes of the file empty
+            let mut file = File::create(&target_path).unwrap();
+            let received = server.recv_file(id, &mut file, 10, u64::MAX).unwrap();
+            server.close(id, Instant::now()).unwrap();
+            received
+        });
+
+        let mut client = endpoint();
+        let id = client.connect(addr, Instant::now()).unwrap();
+        while !client.is_connected(id).unwrap() {
+            client.wait(None).unwrap();
+            client.drive(Instant::now()).unwrap();
+        }
+
+        // Sends everything but the first 100 bytes
+        let mut file = File::open(&source_path).unwrap();
+        let size = data.len() as u64;
+        assert_eq!(
+            client.send_file(id, &mut file, 100, size).unwrap(),
+            size - 100
+        );
+        client.close(id, Instant::now()).unwrap();
+        while client.sockets().next().is_some() {
+            client.wait(None).unwrap();
+            client.drive(Instant::now()).unwrap();
+        }
+
+        assert_eq!(receiver.join().unwrap(), size - 100);
+        let target = std::fs::read(temp_path("target")).unwrap();
+        assert_eq!(target[..10], [0; 10]);
+        assert_eq!(target[10..], data[100..]);
+
+        std::fs::remove_file(source_path).unwrap();
+        std::fs::remove_file(temp_path("target")).unwrap();
+    }
+
+    #[test]
+    fn connect_without_listener_is_rejected() {
+        let server = endpoint();
+        let mut client = endpoint();
+        let now = Instant::now();
+        let id = client.connect(server.local_addr().unwrap(), now).unwrap();
+
+        let mut server = server;
+        server.wait(Some(now + Duration::from_secs(1))).unwrap();
+        server.drive(now).unwrap();
+        client.wait(Some(now + Duration::from_secs(1))).unwrap();
+        client.drive(now).unwrap();
+
+        let error = client.is_connected(id).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+        assert_eq!(
+            client.send(id, b"data").unwrap_err().kind(),
+            io::ErrorKind::ConnectionRefused
+        );
+        client.close(id, now).unwrap();
+        assert!(client.is_connected(id).is_err());
+    }
+
+    #[cfg(feature = "mio")]
+    #[test]
+    fn mio_poll() {
+        use crate::poll::{Interest, Target, UdtPoll};
+
+        fn mio_endpoint() -> Endpoint<mio::net::UdpSocket> {
+            let socket = mio::net::UdpSocket::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+            Endpoint::new(socket, Default::default()).unwrap()
+        }
+
+        let mut poll = mio::Poll::new().unwrap();
+        let mut events = mio::Events::with_capacity(16);
+        let mut server = mio_endpoint();
+        let mut client = mio_endpoint();
+        poll.registry()
+            .register(&mut server, mio::Token(0), mio::Interest::READABLE)
+            .unwrap();
+        poll.registry()
+            .register(&mut client, mio::Token(1), mio::Interest::READABLE)
+            .unwrap();
+
+        server.listen(1);
+        let mut server_poll = UdtPoll::new();
+        server_poll.add(Target::Listener, Interest::READ).unwrap();
+        let client_id = client
+            .connect(server.local_addr().unwrap(), Instant::now())
+            .unwrap();
+        let mut client_poll = UdtPoll::new();
+        client_poll
+            .add(Target::Socket(client_id), Interest::WRITE)
+            .unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut ready = Vec::new();
+        let mut received = Vec::new();
+        let mut server_id = None;
+        let mut sent = false;
+        while received.len() < 4 {
+            assert!(Instant::now() < deadline);
+            let timeout = [server.timeout(), client.timeout()]
+                .into_iter()
+                .flatten()
+                .min()
+                .map(|timeout| timeout.saturating_duration_since(Instant::now()));
+            poll.poll(&mut events, timeout).unwrap();
+
+            server_poll
+                .wait(&mut server, &mut ready, Some(Duration::ZERO))
+                .unwrap();
+            for event in &ready {
+                match event.target {
+                    Target::Listener => {
+                        let (id, _) = server.accept().unwrap();
+                        server_id = Some(id);
+                    }
+                    Target::Socket(id) => {
+                        let mut buffer = [0; 16];
+                        let len = server.recv(id, &mut buffer).unwrap();
+                        received.extend_from_slice(&buffer[..len]);
+                    }
+                }
+            }
+            if let Some(id) = server_id.take() {
+                server_poll.remove(Target::Listener).unwrap();
+                server_poll.add(Target::Socket(id), Interest::READ).unwrap();
+            }
+
+            client_poll
+                .wait(&mut client, &mut ready, Some(Duration::ZERO))
+                .unwrap();
+            if !sent && !ready.is_empty() {
+                assert_eq!(client.send(client_id, b"data").unwrap(), 4);
+                sent = true;
+                client.drive(Instant::now()).unwrap();
+            }
+        }
+        assert_eq!(received, b"data");
+    }
+
+    /// Sends `data` from a client to a server over a simulated network with the impairments
+    /// of `link`, returns the received data, the virtual transfer time and the stats of the
+    /// data link
+    fn sim_transfer(seed: u64, link: LinkConfig, data: &[u8]) -> (Vec<u8>, Duration, LinkStats) {
+        let server_addr = SocketAddr::from(([10, 0, 0, 1], 9000));
+        let client_addr = SocketAddr::from(([10, 0, 0, 2], 9000));
+        let network = SimNetwork::new(seed);
+        network.set_default_link(link);
+
+        let config = |seed| EndpointConfig {
+            seed: Some(seed),
+            ..Default::default()
+        };
+        let mut server = Endpoint::new(network.bind(server_addr).unwrap(), config(seed)).unwrap();
+        let mut client =
+            Endpoint::new(network.bind(client_addr).unwrap(), config(seed + 1)).unwrap();
+        server.listen(1);
+        let client_id = client.connect(server_addr, network.instant()).unwrap();
+
+        let mut server_id = None;
+        let mut sent = 0;
+        let mut received = Vec::new();
+        let mut buffer = [0; 4096];
+        loop {
+            assert!(network.now() < Duration::from_secs(120), "transfer stalled");
+            let now = network.instant();
+            server.drive(now).unwrap();
+            client.drive(now).unwrap();
+
+            if server_id.is_none() {
+                server_id = server.accept().ok().map(|(id, _)| id);
+            }
+            if let Some(id) = server_id {
+                match server.recv(id, &mut buffer) {
+                    Ok(0) => break,
+                    Ok(len) => received.extend_from_slice(&buffer[..len]),
+                    Err(e) => assert_eq!(e.kind(), io::ErrorKind::WouldBlock),
+                }
+            }
+            if sent < data.len() && client.is_connected(client_id).unwrap() {
+                match client.send(client_id, &data[sent..]) {
+                    Ok(len) => sent += len,
+                    Err(e) => assert_eq!(e.kind(), io::ErrorKind::WouldBlock),
+                }
+                if sent == data.len() {
+                    client.close(client_id, now).unwrap();
+                }
+            }
+
+            // Runs until the next packet arrival or timer, a ready socket is polled again
+            if server_id.is_some_and(|id| server.connection(id).unwrap().is_readable()) {
+                continue;
+            }
+            let next = [server.timeout(), client.timeout()]
+                .into_iter()
+                .flatten()
+                .chain(
+                    network
+                        .next_arrival()
+                        .map(|arrival| now + arrival.saturating_sub(network.now())),
+                )
+                .min()
+                .unwrap_or(now);
+            network.advance(
+                next.saturating_duration_since(now)
+                    .max(Duration::from_micros(10)),
+            );
+        }
+
+        let elapsed = network.now();
+        (
+            received,
+            elapsed,
+            network.link_stats(client_addr, server_addr),
+        )
+    }
+
+    fn sim_data() -> Vec<u8> {
+        (0..1_000_000u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn sim_transfer_with_impairments() {
+        let link = LinkConfig {
+            latency: Duration::from_millis(20),
+            jitter: Duration::from_millis(2),
+            loss_rate: 0.02,
+            reorder_rate: 0.01,
+            duplicate_rate: 0.01,
+            bandwidth: Some(2_000_000),
+            queue_limit: Some(100_000),
+            ..Default::default()
+        };
+        let data = sim_data();
+        let (received, elapsed, stats) = sim_transfer(1, link, &data);
+
+        assert!(received == data, "received data differs");
+        assert!(stats.lost > 0 && stats.duplicated > 0);
+        // The transfer takes at least the time to push the data through the link
+        assert!(elapsed > Duration::from_millis(500), "{elapsed:?}");
+    }
+
+    #[test]
+    fn sim_transfer_with_burst_loss() {
+        let link = LinkConfig {
+            burst_loss: Some(BurstLoss {
+                enter_probability: 0.01,
+                exit_probability: 0.3,
+                loss_rate: 0.8,
+            }),
+            ..Default::default()
+        };
+        let data = sim_data();
+        let (received, _, stats) = sim_transfer(2, link, &data);
+
+        assert!(received == data, "received data differs");
+        assert!(stats.lost > 0);
+    }
+
+    #[test]
+    fn sim_transfer_is_reproducible() {
+        let link = LinkConfig {
+            jitter: Duration::from_millis(5),
+            loss_rate: 0.05,
+            reorder_rate: 0.05,
+            ..Default::default()
+        };
+        let data = &sim_data()[..200_000];
+        let runs = [3, 3, 4].map(|seed| {
+            let (received, elapsed, stats) = sim_transfer(seed, link, data);
+            assert!(received == data, "received data differs");
+            (elapsed, stats)
+        });
+
+        assert_eq!(runs[0], runs[1]);
+        assert_ne!(runs[0], runs[2]);
+    }
+}
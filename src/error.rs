@@ -1,4 +1,4 @@
-#[derive(Debug, thiserror::Error)]
+#[derive(Debug, Copy, Clone, thiserror::Error)]
 pub enum ConnectionSetupError {
     #[error("Connection setup error: connection time out")]
     ConnectionTimeOut,
@@ -18,4 +18,65 @@ pub enum ConnectionError {
     Broken,
     #[error("Connection does not exist")]
     NotExist,
+    #[error("Connection linger timeout is too large")]
+    InvalidLinger,
+}
+
+impl From<ConnectionSetupError> for std::io::Error {
+    fn from(error: ConnectionSetupError) -> Self {
+        let kind = match error {
+            ConnectionSetupError::ConnectionTimeOut => std::io::ErrorKind::TimedOut,
+            ConnectionSetupError::ConnectionRejected => std::io::ErrorKind::ConnectionRefused,
+            _ => std::io::ErrorKind::Other,
+        };
+        Self::new(kind, error)
+    }
+}
+
+impl From<ConnectionError> for std::io::Error {
+    fn from(error: ConnectionError) -> Self {
+        let kind = match error {
+            ConnectionError::Failure => std::io::ErrorKind::Other,
+            ConnectionError::Broken => std::io::ErrorKind::ConnectionReset,
+            ConnectionError::NotExist => std::io::ErrorKind::NotConnected,
+            ConnectionError::InvalidLinger => std::io::ErrorKind::InvalidInput,
+        };
+        Self::new(kind, error)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PacketError {
+    #[error("Packet error: buffer too small (needed {needed}, got {got})")]
+    BufferTooSmall { needed: usize, got: usize },
+    #[error("Packet error: unsupported UDT version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Packet error: invalid socket type {0}")]
+    InvalidSocketType(u8),
+    #[error("Packet error: invalid length")]
+    InvalidLength,
+    #[error("Packet error: unknown control type {0}")]
+    UnknownControlType(u16),
+    #[error("Packet error: unexpected control packet")]
+    UnexpectedControlPacket,
+    #[error("Packet error: unexpected data packet")]
+    UnexpectedDataPacket,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WindowError {
+    #[error("Window error: size {size} is less than the minimum of {min}")]
+    TooSmall { size: usize, min: usize },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EstimatorError {
+    #[error("Estimator error: gain {0} is not in (0, 1]")]
+    InvalidGain(f64),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CongestionControlError {
+    #[error("Congestion control error: invalid config, {0}")]
+    InvalidConfig(&'static str),
 }
@@ -0,0 +1,395 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::ack::INITIAL_RTT;
+use crate::cc::{AckSample, CongestionControl};
+use crate::error::CongestionControlError;
+
+#[derive(Debug, Copy, Clone)]
+pub struct LedbatConfig {
+    /// Queueing delay the controller aims for
+    pub target: Duration,
+    /// Window increase per RTT at zero queueing delay (in packets)
+    pub gain: f64,
+    /// Window growth allowed beyond the data in flight per acknowledged packet
+    pub allowed_increase: u32,
+    pub initial_window: u32,
+    pub min_window: u32,
+    /// Number of one-way delay samples the current delay is the minimum of
+    pub current_filter: usize,
+    /// Number of `base_interval` minimums the base delay is the minimum of
+    pub base_history: usize,
+    pub base_interval: Duration,
+}
+
+impl Default for LedbatConfig {
+    fn default() -> Self {
+        Self {
+            target: Duration::from_millis(25),
+            gain: 1.0,
+            allowed_increase: 1,
+            initial_window: 2,
+            min_window: 2,
+            current_filter: 4,
+            base_history: 10,
+            base_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Scavenger congestion control (LEDBAT, RFC 6817).
+///
+/// Compares the one-way delay reported by the receiver against the lowest delay seen
+/// during the last minutes (the base delay) and grows the window while the difference,
+/// the queueing delay, is below the target. Above the target the window shrinks in
+/// proportion to the excess delay (up to halving per RTT, as in LEDBAT++), so the transfer
+/// yields to other traffic before the bottleneck drops packets.
+///
+/// The one-way delay is a nonstandard ACK field. Without delay samples the window grows
+/// like Reno: slow start until the first loss, then `gain` packets per RTT.
+#[derive(Debug)]
+pub struct Ledbat {
+    config: LedbatConfig,
+    /// Window (in packets)
+    window: f64,
+    slow_start: bool,
+    rtt: Duration,
+    /// Latest one-way delays (in microseconds)
+    current_delays: VecDeque<u32>,
+    /// Minimum one-way delay of each base interval (in microseconds)
+    base_delays: VecDeque<u32>,
+    base_interval_start: Instant,
+    last_reduction: Option<Instant>,
+}
+
+impl Ledbat {
+    pub fn new(config: LedbatConfig, now: Instant) -> Result<Self, CongestionControlError> {
+        let check = |valid: bool, error: &'static str| {
+            if valid {
+                Ok(())
+            } else {
+                Err(CongestionControlError::InvalidConfig(error))
+            }
+        };
+        check(!config.target.is_zero(), "target must not be zero")?;
+        check(config.gain > 0.0, "gain must be positive")?;
+        check(config.min_window > 0, "min_window must not be zero")?;
+        check(
+            config.initial_window >= config.min_window,
+            "initial_window must not be less than min_window",
+        )?;
+        check(config.current_filter > 0, "current_filter must not be zero")?;
+        check(config.base_history > 0, "base_history must not be zero")?;
+
+        Ok(Self {
+            config,
+            window: config.initial_window as f64,
+            slow_start: true,
+            rtt: INITIAL_RTT,
+            current_delays: VecDeque::with_capacity(config.current_filter),
+            base_delays: VecDeque::with_capacity(config.base_history),
+            base_interval_start: now,
+            last_reduction: None,
+        })
+    }
+
+    /// Queueing delay estimate (current delay minus base delay)
+    pub fn queueing_delay(&self) -> Option<Duration> {
+        let current = min_delay(&self.current_delays)?;
+        let base = min_delay(&self.base_delays)?;
+
+        // Delays are compared modulo 2^32 like the timestamps they come from
+        let queueing = (current.wrapping_sub(base) as i32).max(0);
+        Some(Duration::from_micros(queueing as u64))
+    }
+
+    fn on_delay(&mut self, now: Instant, delay: u32) {
+        if self.current_delays.len() == self.config.current_filter {
+            self.current_delays.pop_front();
+        }
+        self.current_delays.push_back(delay);
+
+        let next_interval =
+            now.saturating_duration_since(self.base_interval_start) >= self.config.base_interval;
+        match self.base_delays.back_mut() {
+            Some(base) if !next_interval => {
+                if is_before(delay, *base) {
+                    *base = delay;
+                }
+            }
+            _ => {
+                if self.base_delays.len() == self.config.base_history {
+                    self.base_delays.pop_front();
+                }
+                self.base_delays.push_back(delay);
+                self.base_interval_start = now;
+            }
+        }
+    }
+
+    /// Multiplicative decrease, at most once per RTT
+    fn reduce(&mut self, now: Instant, window: f64) {
+        if self
+            .last_reduction
+            .is_some_and(|last| now.saturating_duration_since(last) < self.rtt)
+        {
+            return;
+        }
+
+        self.window = window.max(self.config.min_window as f64);
+        self.last_reduction = Some(now);
+    }
+}
+
+impl CongestionControl for Ledbat {
+    fn on_ack(&mut self, now: Instant, ack: &AckSample) {
+        if !ack.rtt.is_zero() {
+            self.rtt = ack.rtt;
+        }
+        if let Some(delay) = ack.one_way_delay {
+            self.on_delay(now, delay);
+        }
+
+        let target = self.config.target.as_secs_f64();
+        let off_target = self
+            .queueing_delay()
+            .map(|queueing_delay| (target - queueing_delay.as_secs_f64()) / target);
+        if off_target.is_some_and(|off_target| off_target < 0.25) {
+            self.slow_start = false;
+        }
+
+        let acked = ack.acked as f64;
+        if self.slow_start {
+            self.window += acked;
+        } else {
+            // Without delay samples the queue is assumed empty (Reno congestion avoidance)
+            let off_target = off_target.unwrap_or(1.0);
+            let change_per_rtt = if off_target >= 0.0 {
+                self.config.gain * off_target
+            } else {
+                (self.window * off_target).max(-self.window / 2.0)
+            };
+            self.window += change_per_rtt * acked / self.window;
+        }
+
+        // Only grow the window if it's actually used
+        let flight_size = ack.in_flight + ack.acked;
+        let max_window = (flight_size + ack.acked * self.config.allowed_increase) as f64;
+        self.window = self
+            .window
+            .min(max_window)
+            .max(self.config.min_window as f64);
+    }
+
+    fn on_loss(&mut self, now: Instant, _lost: u32) {
+        self.slow_start = false;
+        self.reduce(now, self.window / 2.0);
+    }
+
+    fn on_timeout(&mut self, now: Instant) {
+        self.slow_start = false;
+        self.last_reduction = None;
+        self.reduce(now, self.config.min_window as f64);
+    }
+
+    fn pacing_interval(&self) -> Duration {
+        self.rtt.div_f64(self.window)
+    }
+
+    fn window_size(&self) -> u32 {
+        self.window as u32
+    }
+}
+
+/// `true` if `a` is less than `b` modulo 2^32
+fn is_before(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn min_delay(delays: &VecDeque<u32>) -> Option<u32> {
+    delays
+        .iter()
+        .copied()
+        .reduce(|min, delay| if is_before(delay, min) { delay } else { min })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cc::harness::{transfer, PACKET_SIZE};
+    use crate::packet::HEADER_SIZE;
+    use crate::sim::LinkConfig;
+
+    /// 10 Mbit/s
+    const BANDWIDTH: u64 = 1_250_000;
+
+    #[test]
+    fn keeps_queueing_delay_near_target() {
+        // 40 ms RTT, BDP is about 34 packets, the buffer holds 200 packets (235 ms)
+        let link = LinkConfig {
+            latency: Duration::from_millis(20),
+            bandwidth: Some(BANDWIDTH),
+            queue_limit: Some(200 * PACKET_SIZE),
+            ..Default::default()
+        };
+
+        let mut ledbat = Ledbat::new(LedbatConfig::default(), Instant::now()).unwrap();
+        let mut max_estimate = Duration::ZERO;
+        let stats = transfer(
+            &mut ledbat,
+            link,
+            Duration::from_secs(12),
+            Duration::from_secs(4),
+            |ledbat, now| {
+                if now >= Duration::from_secs(4) {
+                    max_estimate = max_estimate.max(ledbat.queueing_delay().unwrap());
+                }
+            },
+        );
+
+        let capacity = BANDWIDTH as f64 * (PACKET_SIZE - HEADER_SIZE) as f64 / PACKET_SIZE as f64;
+        assert!(stats.throughput > capacity * 0.9, "{stats:?}");
+        // The queue settles around the target instead of filling the buffer
+        let target = LedbatConfig::default().target;
+        assert!(
+            stats.queue_delay > target / 2 && stats.queue_delay < target * 3 / 2,
+            "{stats:?}"
+        );
+        assert!(stats.max_queue_delay < target * 2, "{stats:?}");
+        assert!(max_estimate < target * 2, "{max_estimate:?}");
+        assert_eq!(stats.dropped, 0);
+    }
+
+    #[test]
+    fn yields_to_growing_queue() {
+        let start = Instant::now();
+        let mut ledbat = Ledbat::new(LedbatConfig::default(), start).unwrap();
+        // 40 ms RTT with an ACK every 10 ms, the application sends at most 300 packets per RTT
+        let on_ack = |ledbat: &mut Ledbat, now, one_way_delay| {
+            let in_flight = ledbat.window_size().min(300);
+            let acked = (in_flight / 4).max(1);
+            let sample = AckSample {
+                acked,
+                in_flight: in_flight - acked,
+                rtt: Duration::from_millis(40),
+                rtt_sample: None,
+                delivery_rate: None,
+                one_way_delay: Some(one_way_delay),
+            };
+            ledbat.on_ack(now, &sample);
+        };
+
+        // Raw delays include the clock offset between the peers and wrap around
+        let base = u32::MAX - 10_000;
+        let mut now = start;
+        for _ in 0..100 {
+            now += Duration::from_millis(10);
+            on_ack(&mut ledbat, now, base);
+        }
+        assert_eq!(ledbat.queueing_delay(), Some(Duration::ZERO));
+        let window = ledbat.window_size();
+        assert!(window > 300, "{window}");
+
+        // Other traffic fills the queue to twice the target, the window halves every RTT
+        for _ in 0..60 {
+            now += Duration::from_millis(10);
+            on_ack(&mut ledbat, now, base.wrapping_add(50_000));
+        }
+        assert_eq!(ledbat.queueing_delay(), Some(Duration::from_millis(50)));
+        assert_eq!(ledbat.window_size(), 2);
+
+        // Grows again once the queue drains
+        for _ in 0..10 {
+            now += Duration::from_millis(10);
+            on_ack(&mut ledbat, now, base.wrapping_add(5_000));
+        }
+        assert_eq!(ledbat.queueing_delay(), Some(Duration::from_millis(5)));
+        assert!(ledbat.window_size() > 2);
+    }
+
+    #[test]
+    fn grows_like_reno_without_delay_samples() {
+        let start = Instant::now();
+        let mut ledbat = Ledbat::new(LedbatConfig::default(), start).unwrap();
+        let mut now = start;
+        let mut on_ack = |ledbat: &mut Ledbat| {
+            now += Duration::from_millis(40);
+            let in_flight = ledbat.window_size();
+            ledbat.on_ack(
+                now,
+                &AckSample {
+                    acked: in_flight,
+                    in_flight: 0,
+                    rtt: Duration::from_millis(40),
+                    rtt_sample: None,
+                    delivery_rate: None,
+                    one_way_delay: None,
+                },
+            );
+            now
+        };
+
+        // Slow start doubles the window every RTT
+        for _ in 0..5 {
+            on_ack(&mut ledbat);
+        }
+        assert_eq!(ledbat.queueing_delay(), None);
+        assert_eq!(ledbat.window_size(), 64);
+
+        let now = on_ack(&mut ledbat);
+        ledbat.on_loss(now, 1);
+        assert_eq!(ledbat.window_size(), 64);
+
+        // One packet per RTT after the loss
+        for _ in 0..10 {
+            on_ack(&mut ledbat);
+        }
+        assert_eq!(ledbat.window_size(), 74);
+        assert!(ledbat.pacing_interval() > Duration::ZERO);
+    }
+
+    #[test]
+    fn config_is_validated() {
+        let now = Instant::now();
+        let invalid = [
+            LedbatConfig {
+                min_window: 0,
+                initial_window: 0,
+                ..Default::default()
+            },
+            LedbatConfig {
+                initial_window: 1,
+                ..Default::default()
+            },
+            LedbatConfig {
+                target: Duration::ZERO,
+                ..Default::default()
+            },
+            LedbatConfig {
+                gain: 0.0,
+                ..Default::default()
+            },
+            LedbatConfig {
+                current_filter: 0,
+                ..Default::default()
+            },
+            LedbatConfig {
+                base_history: 0,
+                ..Default::default()
+            },
+        ];
+        for config in invalid {
+            assert!(matches!(
+                Ledbat::new(config, now),
+                Err(CongestionControlError::InvalidConfig(_))
+            ));
+        }
+
+        let config = LedbatConfig {
+            min_window: 1,
+            initial_window: 1,
+            ..Default::default()
+        };
+        assert!(Ledbat::new(config, now).is_ok());
+    }
+}
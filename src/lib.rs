@@ -1,6 +1,31 @@
-mod error;
-mod packet;
-mod window;
+pub mod ack;
+pub mod bandwidth;
+#[cfg(all(target_os = "linux", feature = "linux-batch"))]
+pub mod batch;
+pub mod bbr;
+pub mod cc;
+pub mod connection;
+pub mod endpoint;
+pub mod error;
+pub mod ledbat;
+pub mod message;
+#[cfg(all(target_os = "linux", feature = "linux-gso"))]
+pub mod offload;
+pub mod packet;
+pub mod packet_ref;
+pub mod poll;
+pub mod pool;
+pub mod seq;
+pub mod sim;
+pub mod state;
+#[cfg(all(
+    target_os = "linux",
+    any(feature = "linux-batch", feature = "linux-gso")
+))]
+mod sys;
+pub mod timer;
+pub mod transport;
+pub mod window;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,164 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::packet::{MessageBoundary, MessageDropRequestControlInfo};
+use crate::seq::{msg_inc, seq_add, seq_cmp, seq_inc};
+
+/// Message which is waiting in the send buffer
+#[derive(Debug, Copy, Clone)]
+pub struct OutgoingMessage {
+    /// Message number
+    pub msg_no: u32,
+    /// First sequence number in the message
+    pub first_seq_no: u32,
+    /// Last sequence number in the message
+    pub last_seq_no: u32,
+    /// The time when the message was submitted
+    pub origin_time: Instant,
+    /// Optional time to live
+    pub ttl: Option<Duration>,
+    /// Whether the message must be delivered in order
+    pub in_order: bool,
+}
+
+impl OutgoingMessage {
+    pub fn is_expired(&self, now: Instant) -> bool {
+        matches!(self.ttl, Some(ttl) if now.saturating_duration_since(self.origin_time) > ttl)
+    }
+
+    /// Position of the packet within the message
+    pub fn boundary(&self, seq_no: u32) -> MessageBoundary {
+        match (seq_no == self.first_seq_no, seq_no == self.last_seq_no) {
+            (true, true) => MessageBoundary::Solo,
+            (true, false) => MessageBoundary::First,
+            (false, true) => MessageBoundary::Last,
+            (false, false) => MessageBoundary::Middle,
+        }
+    }
+
+    /// Request for the receiver to skip this message
+    pub fn drop_request(&self) -> MessageDropRequestControlInfo {
+        MessageDropRequestControlInfo::new(self.first_seq_no, self.last_seq_no)
+    }
+}
+
+/// Sender side message bookkeeping for datagram sockets
+#[derive(Debug)]
+pub struct MessageList {
+    messages: VecDeque<OutgoingMessage>,
+    /// Sequence number of the first packet of the next message
+    next_seq_no: u32,
+    /// Next message number
+    next_msg_no: u32,
+}
+
+impl MessageList {
+    pub fn new(isn: u32) -> Self {
+        Self {
+            messages: Default::default(),
+            next_seq_no: isn,
+            next_msg_no: 1,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Registers a message of `packet_count` packets
+    pub fn push(
+        &mut self,
+        packet_count: u32,
+        ttl: Option<Duration>,
+        in_order: bool,
+        now: Instant,
+    ) -> OutgoingMessage {
+        let packet_count = packet_count.max(1);
+
+        let message = OutgoingMessage {
+            msg_no: self.next_msg_no,
+            first_seq_no: self.next_seq_no,
+            last_seq_no: seq_add(self.next_seq_no, packet_count - 1),
+            origin_time: now,
+            ttl,
+            in_order,
+        };
+        self.messages.push_back(message);
+
+        self.next_seq_no = seq_inc(message.last_seq_no);
+        self.next_msg_no = msg_inc(self.next_msg_no);
+
+        message
+    }
+
+    /// Finds the message which contains the packet
+    pub fn find(&self, seq_no: u32) -> Option<&OutgoingMessage> {
+        self.messages.iter().find(|message| {
+            seq_cmp(seq_no, message.first_seq_no) >= 0 && seq_cmp(seq_no, message.last_seq_no) <= 0
+        })
+    }
+
+    /// Removes messages which were fully received by the peer
+    pub fn acknowledge(&mut self, received_last_ack: u32) {
+        while let Some(message) = self.messages.front() {
+            if seq_cmp(message.last_seq_no, received_last_ack) >= 0 {
+                break;
+            }
+            self.messages.pop_front();
+        }
+    }
+
+    /// Removes messages with expired TTL.
+    ///
+    /// A Message Drop Request must be sent for each of them
+    pub fn drop_expired(&mut self, now: Instant) -> Vec<OutgoingMessage> {
+        let mut expired = Vec::new();
+        self.messages.retain(|message| {
+            if message.is_expired(now) {
+                expired.push(*message);
+                false
+            } else {
+                true
+            }
+        });
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seq::MAX_SEQ_NO;
+
+    #[test]
+    fn expired_messages_are_dropped() {
+        let now = Instant::now();
+        let ttl = Duration::from_millis(100);
+
+        let mut messages = MessageList::new(MAX_SEQ_NO - 1);
+        let first = messages.push(3, Some(ttl), false, now);
+        let second = messages.push(1, None, true, now);
+        assert_eq!((first.first_seq_no, first.last_seq_no), (MAX_SEQ_NO - 1, 0));
+        assert_eq!((second.first_seq_no, second.last_seq_no), (1, 1));
+        assert_eq!(first.boundary(MAX_SEQ_NO), MessageBoundary::Middle);
+        assert_eq!(second.boundary(1), MessageBoundary::Solo);
+
+        assert!(messages.drop_expired(now + ttl).is_empty());
+
+        let expired = messages.drop_expired(now + ttl * 2);
+        assert_eq!(expired.len(), 1);
+        let request = expired[0].drop_request();
+        assert_eq!(request.first_seq_no(), MAX_SEQ_NO - 1);
+        assert_eq!(request.last_seq_no(), 0);
+
+        assert_eq!(messages.find(1).unwrap().msg_no, second.msg_no);
+        assert!(messages.find(0).is_none());
+
+        messages.acknowledge(2);
+        assert!(messages.is_empty());
+    }
+}
@@ -0,0 +1,402 @@
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::os::fd::AsRawFd;
+
+use crate::sys::{read_sockaddr, sockaddr_len, write_sockaddr};
+
+/// Maximum number of segments the kernel accepts in a single GSO send
+pub const MAX_GSO_SEGMENTS: usize = 64;
+/// Maximum payload of a single GSO send (the UDP datagram limit)
+pub const MAX_GSO_SIZE: usize = 65507;
+
+/// UDP socket with optional segmentation (GSO) and receive coalescing (GRO) offload.
+///
+/// When the kernel does not support offload, every segment is sent and received separately.
+#[derive(Debug)]
+pub struct OffloadSocket {
+    socket: UdpSocket,
+    gso: bool,
+    gro: bool,
+}
+
+impl OffloadSocket {
+    /// Wraps the socket and enables offload if supported
+    pub fn new(socket: UdpSocket) -> Self {
+        let gso = get_option(&socket, libc::UDP_SEGMENT).is_ok();
+        let gro = set_option(&socket, libc::UDP_GRO, 1).is_ok();
+        Self { socket, gso, gro }
+    }
+
+    /// Wraps the socket without any offload
+    pub fn without_offload(socket: UdpSocket) -> Self {
+        Self {
+            socket,
+            gso: false,
+            gro: false,
+        }
+    }
+
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    pub fn gso_enabled(&self) -> bool {
+        self.gso
+    }
+
+    pub fn gro_enabled(&self) -> bool {
+        self.gro
+    }
+
+    /// Sends a buffer of equal-sized packets (only the last one may be shorter).
+    ///
+    /// Large buffers are split into several GSO sends of at most [`MAX_GSO_SEGMENTS`]
+    /// segments and [`MAX_GSO_SIZE`] bytes. Falls back to per-packet sends if GSO is
+    /// unavailable or rejected by the device
+    pub fn send_segments(
+        &mut self,
+        buffer: &[u8],
+        segment_size: usize,
+        addr: SocketAddr,
+    ) -> io::Result<usize> {
+        if segment_size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "segment size must not be zero",
+            ));
+        }
+        let Ok(gso_segment_size) = u16::try_from(segment_size) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "segment size exceeds 65535 bytes",
+            ));
+        };
+
+        let gso_size = segment_size * (MAX_GSO_SIZE / segment_size).min(MAX_GSO_SEGMENTS);
+        let mut sent = 0;
+        let mut offset = 0;
+
+        // At least two segments per send, otherwise GSO is pointless
+        while self.gso && gso_size > segment_size && buffer.len() - offset > segment_size {
+            let chunk = &buffer[offset..buffer.len().min(offset + gso_size)];
+            match send_gso(&self.socket, chunk, gso_segment_size, addr) {
+                Ok(len) => {
+                    sent += len;
+                    offset += chunk.len();
+                }
+                // Device doesn't support checksum offload or GSO is disabled
+                Err(e) if matches!(e.raw_os_error(), Some(libc::EIO | libc::EINVAL)) => {
+                    self.gso = false;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        for segment in buffer[offset..].chunks(segment_size) {
+            sent += self.socket.send_to(segment, addr)?;
+        }
+        Ok(sent)
+    }
+
+    /// Receives a datagram which may contain several coalesced packets.
+    ///
+    /// The buffer must be large enough for the coalesced data (64 KiB), otherwise a
+    /// truncated GRO datagram is reported as an error
+    pub fn recv_segments<'a>(&self, buffer: &'a mut [u8]) -> io::Result<Segments<'a>> {
+        if !self.gro {
+            let (len, addr) = self.socket.recv_from(buffer)?;
+            return Ok(Segments {
+                data: &buffer[..len],
+                segment_size: len.max(1),
+                addr,
+            });
+        }
+
+        let (len, segment_size, addr) = recv_gro(&self.socket, buffer)?;
+        Ok(Segments {
+            data: &buffer[..len],
+            segment_size: segment_size.unwrap_or(len).max(1),
+            addr,
+        })
+    }
+}
+
+/// Received packets split by the GRO segment size
+#[derive(Debug, Copy, Clone)]
+pub struct Segments<'a> {
+    data: &'a [u8],
+    segment_size: usize,
+    addr: SocketAddr,
+}
+
+impl<'a> Segments<'a> {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn segment_size(&self) -> usize {
+        self.segment_size
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len().div_ceil(self.segment_size)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Chunks<'a, u8> {
+        self.data.chunks(self.segment_size)
+    }
+}
+
+fn send_gso(
+    socket: &UdpSocket,
+    buffer: &[u8],
+    segment_size: u16,
+    addr: SocketAddr,
+) -> io::Result<usize> {
+    // SAFETY: zeroes are valid for these C structs
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    write_sockaddr(&addr, &mut storage);
+
+    let mut iovec = libc::iovec {
+        iov_base: buffer.as_ptr() as *mut libc::c_void,
+        iov_len: buffer.len(),
+    };
+    let mut control = [0u64; 4];
+
+    // SAFETY: zeroes are valid for `msghdr`
+    let mut header: libc::msghdr = unsafe { std::mem::zeroed() };
+    header.msg_name = &mut storage as *mut _ as *mut libc::c_void;
+    header.msg_namelen = sockaddr_len(&storage);
+    header.msg_iov = &mut iovec;
+    header.msg_iovlen = 1;
+    header.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    // SAFETY: `CMSG_SPACE` is a pure computation
+    header.msg_controllen = unsafe { libc::CMSG_SPACE(std::mem::size_of::<u16>() as _) } as _;
+
+    // SAFETY: control buffer is large enough and aligned for one `u16` message
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&header);
+        (*cmsg).cmsg_level = libc::SOL_UDP;
+        (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<u16>() as _) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut u16, segment_size);
+    }
+
+    loop {
+        // SAFETY: all pointers in the header are valid during the call
+        let result = unsafe { libc::sendmsg(socket.as_raw_fd(), &header, 0) };
+        if result >= 0 {
+            return Ok(result as usize);
+        }
+
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+}
+
+fn recv_gro(
+    socket: &UdpSocket,
+    buffer: &mut [u8],
+) -> io::Result<(usize, Option<usize>, SocketAddr)> {
+    // SAFETY: zeroes are valid for these C structs
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+
+    let mut iovec = libc::iovec {
+        iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buffer.len(),
+    };
+    let mut control = [0u64; 8];
+
+    // SAFETY: zeroes are valid for `msghdr`
+    let mut header: libc::msghdr = unsafe { std::mem::zeroed() };
+    header.msg_name = &mut storage as *mut _ as *mut libc::c_void;
+    header.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as _;
+    header.msg_iov = &mut iovec;
+    header.msg_iovlen = 1;
+    header.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    header.msg_controllen = std::mem::size_of_val(&control) as _;
+
+    let len = loop {
+        // SAFETY: all pointers in the header are valid during the call
+        let result = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut header, 0) };
+        if result >= 0 {
+            break result as usize;
+        }
+
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    };
+
+    if header.msg_flags & libc::MSG_TRUNC != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "buffer too small for the coalesced datagram",
+        ));
+    }
+
+    let mut segment_size = None;
+    // SAFETY: control messages are filled by the kernel within `msg_controllen`
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&header);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_UDP && (*cmsg).cmsg_type == libc::UDP_GRO {
+                let size = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::c_int);
+                segment_size = Some(size as usize);
+            }
+            cmsg = libc::CMSG_NXTHDR(&header, cmsg);
+        }
+    }
+
+    let addr = read_sockaddr(&storage)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unknown address family"))?;
+    Ok((len, segment_size, addr))
+}
+
+fn get_option(socket: &UdpSocket, name: libc::c_int) -> io::Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: value and len point to valid memory
+    let result = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_UDP,
+            name,
+            &mut value as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+fn set_option(socket: &UdpSocket, name: libc::c_int, value: libc::c_int) -> io::Result<()> {
+    // SAFETY: value points to valid memory
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_UDP,
+            name,
+            &value as *const _ as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEGMENT_SIZE: usize = 1000;
+    const SEGMENT_COUNT: usize = 8;
+
+    fn transfer(sender: OffloadSocket, receiver: OffloadSocket) {
+        transfer_segments(sender, receiver, SEGMENT_SIZE, SEGMENT_COUNT);
+    }
+
+    fn transfer_segments(
+        mut sender: OffloadSocket,
+        receiver: OffloadSocket,
+        segment_size: usize,
+        segment_count: usize,
+    ) {
+        let addr = receiver.socket().local_addr().unwrap();
+        receiver
+            .socket()
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+
+        let mut data = vec![0u8; segment_size * segment_count];
+        for (i, segment) in data.chunks_mut(segment_size).enumerate() {
+            segment.fill(i as u8);
+        }
+        assert_eq!(
+            sender.send_segments(&data, segment_size, addr).unwrap(),
+            data.len()
+        );
+
+        let mut buffer = vec![0u8; 65536];
+        let mut received = 0;
+        while received < segment_count {
+            let segments = receiver
+                .recv_segments(&mut buffer)
+                .expect("all segments should arrive");
+            assert_eq!(segments.addr(), sender.socket().local_addr().unwrap());
+            for segment in segments.iter() {
+                assert_eq!(segment, vec![received as u8; segment_size]);
+                received += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn offload_loopback() {
+        let sender = OffloadSocket::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let receiver = OffloadSocket::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        transfer(sender, receiver);
+    }
+
+    #[test]
+    fn fallback_loopback() {
+        let sender = OffloadSocket::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let receiver = OffloadSocket::without_offload(UdpSocket::bind("127.0.0.1:0").unwrap());
+        transfer(sender, receiver);
+
+        let sender = OffloadSocket::without_offload(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let receiver = OffloadSocket::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        transfer(sender, receiver);
+    }
+
+    #[test]
+    fn large_buffer_is_split() {
+        // 64 full-sized packets are more than a single UDP datagram can carry
+        let sender = OffloadSocket::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let receiver = OffloadSocket::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        transfer_segments(sender, receiver, 1456, 2 * MAX_GSO_SEGMENTS + 1);
+    }
+
+    #[test]
+    fn oversized_segment_is_rejected() {
+        let mut sender = OffloadSocket::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let addr = sender.socket().local_addr().unwrap();
+        let error = sender
+            .send_segments(&[0; 140_000], 70_000, addr)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn zero_segment_size_is_rejected() {
+        let mut sender = OffloadSocket::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let addr = sender.socket().local_addr().unwrap();
+        let error = sender.send_segments(&[0; 100], 0, addr).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn truncated_datagram_is_rejected() {
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let receiver = OffloadSocket::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        if !receiver.gro_enabled() {
+            return;
+        }
+
+        let addr = receiver.socket().local_addr().unwrap();
+        sender.send_to(&[0; 1000], addr).unwrap();
+        let error = receiver.recv_segments(&mut [0; 100]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -1,4 +1,9 @@
-#[derive(Debug, Clone, Copy)]
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::error::PacketError;
+use crate::seq::MAX_MSG_NO;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub struct PacketHeader {
     /// Packet sequence number
     pub seq_no: u32,
@@ -10,14 +15,173 @@ pub struct PacketHeader {
     pub id: u32,
 }
 
-#[derive(Debug, Clone, Copy)]
-enum PacketData {
+impl PacketHeader {
+    pub fn serialize<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a [u8], PacketError> {
+        check_size(buffer, HEADER_SIZE)?;
+
+        // 1) 32 bits: flag bit (0 for data packet) and packet sequence number
+        buffer[0..4].copy_from_slice(&(self.seq_no & !CONTROL_FLAG).to_le_bytes());
+
+        // 2) 32 bits: message number
+        buffer[4..8].copy_from_slice(&self.msg_no.to_le_bytes());
+
+        // 3) 32 bits: timestamp
+        buffer[8..12].copy_from_slice(&self.timestamp.to_le_bytes());
+
+        // 4) 32 bits: destination socket ID
+        buffer[12..16].copy_from_slice(&self.id.to_le_bytes());
+
+        Ok(buffer.split_at(HEADER_SIZE).0)
+    }
+
+    pub fn deserialize(buffer: &[u8]) -> Result<Self, PacketError> {
+        check_size(buffer, HEADER_SIZE)?;
+
+        // 1) 32 bits: flag bit (0 for data packet) and packet sequence number
+        let seq_no = u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]);
+        if seq_no & CONTROL_FLAG != 0 {
+            return Err(PacketError::UnexpectedControlPacket);
+        }
+
+        // 2) 32 bits: message number
+        let msg_no = u32::from_le_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]);
+
+        // 3) 32 bits: timestamp
+        let timestamp = u32::from_le_bytes([buffer[8], buffer[9], buffer[10], buffer[11]]);
+
+        // 4) 32 bits: destination socket ID
+        let id = u32::from_le_bytes([buffer[12], buffer[13], buffer[14], buffer[15]]);
+
+        Ok(Self {
+            seq_no,
+            msg_no,
+            timestamp,
+            id,
+        })
+    }
+
+    /// Position of the packet within the message
+    pub fn message_boundary(&self) -> MessageBoundary {
+        match self.msg_no >> 30 {
+            0b10 => MessageBoundary::First,
+            0b01 => MessageBoundary::Last,
+            0b11 => MessageBoundary::Solo,
+            _ => MessageBoundary::Middle,
+        }
+    }
+
+    /// Whether the message must be delivered in order
+    pub fn in_order(&self) -> bool {
+        self.msg_no & IN_ORDER_FLAG != 0
+    }
+
+    /// Message number without boundary and order flags
+    pub fn message_number(&self) -> u32 {
+        self.msg_no & MAX_MSG_NO
+    }
+
+    /// Packs the message number together with boundary and order flags
+    pub fn set_message(&mut self, msg_no: u32, boundary: MessageBoundary, in_order: bool) {
+        let boundary = match boundary {
+            MessageBoundary::Middle => 0b00,
+            MessageBoundary::Last => 0b01,
+            MessageBoundary::First => 0b10,
+            MessageBoundary::Solo => 0b11,
+        };
+
+        self.msg_no = (boundary << 30) | (msg_no & MAX_MSG_NO);
+        if in_order {
+            self.msg_no |= IN_ORDER_FLAG;
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MessageBoundary {
+    /// 00 - Packet in the middle of the message
+    Middle,
+    /// 01 - Last packet of the message
+    Last,
+    /// 10 - First packet of the message
+    First,
+    /// 11 - The only packet of the message
+    Solo,
+}
+
+/// Control packet
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ControlPacket {
+    /// Packet timestamp
+    pub timestamp: u32,
+    /// Destination socket ID
+    pub id: u32,
+    /// Control info together with the additional info from the header
+    pub data: PacketData,
+}
+
+impl ControlPacket {
+    pub fn serialize<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a [u8], PacketError> {
+        check_size(buffer, HEADER_SIZE)?;
+        let (header, body) = buffer.split_at_mut(HEADER_SIZE);
+        let body_size = self.data.serialize(body)?.len();
+
+        // 1) 32 bits: flag bit (1 for control packet), type and reserved field
+        let control_type = CONTROL_FLAG | ((self.data.control_type() as u32) << 16);
+        header[0..4].copy_from_slice(&control_type.to_le_bytes());
+
+        // 2) 32 bits: additional info
+        header[4..8].copy_from_slice(&self.data.additional_info().to_le_bytes());
+
+        // 3) 32 bits: timestamp
+        header[8..12].copy_from_slice(&self.timestamp.to_le_bytes());
+
+        // 4) 32 bits: destination socket ID
+        header[12..16].copy_from_slice(&self.id.to_le_bytes());
+
+        Ok(buffer.split_at(HEADER_SIZE + body_size).0)
+    }
+
+    pub fn deserialize(buffer: &[u8]) -> Result<Self, PacketError> {
+        check_size(buffer, HEADER_SIZE)?;
+
+        // 1) 32 bits: flag bit (1 for control packet), type and reserved field
+        let control_type = u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]);
+        if control_type & CONTROL_FLAG == 0 {
+            return Err(PacketError::UnexpectedDataPacket);
+        }
+        let control_type = ((control_type & !CONTROL_FLAG) >> 16) as u16;
+
+        // 2) 32 bits: additional info
+        let additional_info = u32::from_le_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]);
+
+        // 3) 32 bits: timestamp
+        let timestamp = u32::from_le_bytes([buffer[8], buffer[9], buffer[10], buffer[11]]);
+
+        // 4) 32 bits: destination socket ID
+        let id = u32::from_le_bytes([buffer[12], buffer[13], buffer[14], buffer[15]]);
+
+        let data = PacketData::deserialize(control_type, additional_info, &buffer[HEADER_SIZE..])?;
+
+        Ok(Self {
+            timestamp,
+            id,
+            data,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PacketData {
     /// 0000 - Handshake
     Handshake(HandshakeControlInfo),
     /// 0001 - Keep-alive
     KeepAlive,
     /// 0010 - Acknowledgement (ACK)
-    Ack(AckControlInfo),
+    Ack {
+        /// ACK sequence number (additional info)
+        ack_seq_no: u32,
+        info: AckControlInfo,
+    },
     /// 0011 - Loss Report (NAK)
     Nak(NakControlInfo),
     /// 0100 - Congestion Warning (unused)
@@ -25,13 +189,85 @@ enum PacketData {
     /// 0101 - Shutdown
     Shutdown,
     /// 0110 - Acknowledgement of Acknowledgement (ACK-2)
-    Ack2,
+    Ack2 {
+        /// ACK sequence number of the acknowledged ACK (additional info)
+        ack_seq_no: u32,
+    },
     /// 0111 - Message Drop Request
-    MessageDropRequest(MessageDropRequestControlInfo),
+    MessageDropRequest {
+        /// Message ID (additional info)
+        msg_no: u32,
+        info: MessageDropRequestControlInfo,
+    },
+}
+
+impl PacketData {
+    /// Control packet type
+    pub fn control_type(&self) -> u16 {
+        match self {
+            Self::Handshake(_) => 0,
+            Self::KeepAlive => 1,
+            Self::Ack { .. } => 2,
+            Self::Nak(_) => 3,
+            Self::CongestionWarning => 4,
+            Self::Shutdown => 5,
+            Self::Ack2 { .. } => 6,
+            Self::MessageDropRequest { .. } => 7,
+        }
+    }
+
+    /// Type specific value of the header additional info field
+    pub fn additional_info(&self) -> u32 {
+        match self {
+            Self::Ack { ack_seq_no, .. } | Self::Ack2 { ack_seq_no } => *ack_seq_no,
+            Self::MessageDropRequest { msg_no, .. } => *msg_no,
+            _ => 0,
+        }
+    }
+
+    /// Writes control info into the buffer
+    pub fn serialize<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a [u8], PacketError> {
+        match self {
+            Self::Handshake(info) => info.serialize(buffer),
+            Self::Ack { info, .. } => info.serialize(buffer),
+            Self::Nak(info) => info.serialize(buffer),
+            Self::MessageDropRequest { info, .. } => info.serialize(buffer),
+            Self::KeepAlive | Self::CongestionWarning | Self::Shutdown | Self::Ack2 { .. } => {
+                Ok(buffer.split_at(0).0)
+            }
+        }
+    }
+
+    /// Reads control info of the specified type from the buffer
+    pub fn deserialize(
+        control_type: u16,
+        additional_info: u32,
+        buffer: &[u8],
+    ) -> Result<Self, PacketError> {
+        Ok(match control_type {
+            0 => Self::Handshake(HandshakeControlInfo::deserialize(buffer)?),
+            1 => Self::KeepAlive,
+            2 => Self::Ack {
+                ack_seq_no: additional_info,
+                info: AckControlInfo::deserialize(buffer)?,
+            },
+            3 => Self::Nak(NakControlInfo::deserialize(buffer)?),
+            4 => Self::CongestionWarning,
+            5 => Self::Shutdown,
+            6 => Self::Ack2 {
+                ack_seq_no: additional_info,
+            },
+            7 => Self::MessageDropRequest {
+                msg_no: additional_info,
+                info: MessageDropRequestControlInfo::deserialize(buffer)?,
+            },
+            ty => return Err(PacketError::UnknownControlType(ty)),
+        })
+    }
 }
 
 /// Message Drop Request packet control info
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct MessageDropRequestControlInfo {
     /// First sequence number in the message
     first_seq_no: u32,
@@ -40,10 +276,23 @@ pub struct MessageDropRequestControlInfo {
 }
 
 impl MessageDropRequestControlInfo {
-    pub fn serialize<'a>(&self, buffer: &'a mut [u8]) -> Option<&'a [u8]> {
-        if buffer.len() < MDR_SIZE {
-            return None;
+    pub fn new(first_seq_no: u32, last_seq_no: u32) -> Self {
+        Self {
+            first_seq_no,
+            last_seq_no,
         }
+    }
+
+    pub fn first_seq_no(&self) -> u32 {
+        self.first_seq_no
+    }
+
+    pub fn last_seq_no(&self) -> u32 {
+        self.last_seq_no
+    }
+
+    pub fn serialize<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a [u8], PacketError> {
+        check_size(buffer, MDR_SIZE)?;
 
         // 1) 32 bits: First sequence number in the message
         buffer[0..4].copy_from_slice(&self.first_seq_no.to_le_bytes());
@@ -51,12 +300,13 @@ impl MessageDropRequestControlInfo {
         // 2) 32 bits: Last sequence number in the message
         buffer[4..8].copy_from_slice(&self.last_seq_no.to_le_bytes());
 
-        Some(buffer.split_at(MDR_SIZE).0)
+        Ok(buffer.split_at(MDR_SIZE).0)
     }
 
-    pub fn deserialize(buffer: &[u8]) -> Option<Self> {
+    pub fn deserialize(buffer: &[u8]) -> Result<Self, PacketError> {
+        check_size(buffer, MDR_SIZE)?;
         if buffer.len() != MDR_SIZE {
-            return None;
+            return Err(PacketError::InvalidLength);
         }
 
         // 1) 32 bits: First sequence number in the message
@@ -65,7 +315,7 @@ impl MessageDropRequestControlInfo {
         // 2) 32 bits: Last sequence number in the message
         let last_seq_no = u32::from_le_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]);
 
-        Some(Self {
+        Ok(Self {
             first_seq_no,
             last_seq_no,
         })
@@ -73,7 +323,7 @@ impl MessageDropRequestControlInfo {
 }
 
 /// Negative-acknowledgment packet control info
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum NakControlInfo {
     Single {
         /// Lost packet seqno
@@ -86,41 +336,68 @@ pub enum NakControlInfo {
 }
 
 impl NakControlInfo {
-    pub fn serialize<'a>(&self, buffer: &'a mut [u8]) -> Option<&'a [u8]> {
+    /// Loss report for the inclusive range `first..=last`
+    pub fn range(first: u32, last: u32) -> Self {
+        if first == last {
+            Self::Single { seq_no: first }
+        } else {
+            Self::Multiple {
+                loss_data: [first | LOSS_RANGE_FLAG, last],
+            }
+        }
+    }
+
+    /// Reported losses as inclusive ranges
+    pub fn ranges(&self) -> impl Iterator<Item = (u32, u32)> {
+        let ranges = match *self {
+            Self::Single { seq_no } => [Some((seq_no, seq_no)), None],
+            Self::Multiple {
+                loss_data: [first, last],
+            } if first & LOSS_RANGE_FLAG != 0 => [Some((first & !LOSS_RANGE_FLAG, last)), None],
+            Self::Multiple {
+                loss_data: [first, second],
+            } => [Some((first, first)), Some((second, second))],
+        };
+        ranges.into_iter().flatten()
+    }
+
+    pub fn serialize<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a [u8], PacketError> {
         match self {
-            Self::Single { seq_no } if buffer.len() >= NAK_SMALL_SIZE => {
+            Self::Single { seq_no } => {
+                check_size(buffer, NAK_SMALL_SIZE)?;
                 buffer[0..4].copy_from_slice(&seq_no.to_le_bytes());
-                Some(buffer.split_at(NAK_SMALL_SIZE).0)
+                Ok(buffer.split_at(NAK_SMALL_SIZE).0)
             }
-            Self::Multiple { loss_data } if buffer.len() >= NAK_BIG_SIZE => {
+            Self::Multiple { loss_data } => {
+                check_size(buffer, NAK_BIG_SIZE)?;
                 buffer[0..4].copy_from_slice(&loss_data[0].to_le_bytes());
                 buffer[4..8].copy_from_slice(&loss_data[1].to_le_bytes());
-                Some(buffer.split_at(NAK_BIG_SIZE).0)
+                Ok(buffer.split_at(NAK_BIG_SIZE).0)
             }
-            _ => None,
         }
     }
 
-    pub fn deserialize(buffer: &mut [u8]) -> Option<Self> {
+    pub fn deserialize(buffer: &[u8]) -> Result<Self, PacketError> {
+        check_size(buffer, NAK_SMALL_SIZE)?;
         if buffer.len() == NAK_BIG_SIZE {
-            Some(Self::Multiple {
+            Ok(Self::Multiple {
                 loss_data: [
                     u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]),
                     u32::from_le_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]),
                 ],
             })
         } else if buffer.len() == NAK_SMALL_SIZE {
-            Some(Self::Single {
+            Ok(Self::Single {
                 seq_no: u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]),
             })
         } else {
-            None
+            Err(PacketError::InvalidLength)
         }
     }
 }
 
 /// Acknowledgement packet control info
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct AckControlInfo {
     /// The packet sequence number to which all the
     /// previous packets have been received (excluding)
@@ -130,11 +407,9 @@ pub struct AckControlInfo {
 }
 
 impl AckControlInfo {
-    pub fn serialize<'a>(&self, buffer: &'a mut [u8]) -> Option<&'a [u8]> {
+    pub fn serialize<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a [u8], PacketError> {
         let mut total_size = ACK_SMALL_SIZE;
-        if buffer.len() < total_size {
-            return None;
-        }
+        check_size(buffer, total_size)?;
 
         // 1) 32 bits: The packet sequence number to which all the previous packets
         //    have been received (excluding)
@@ -142,9 +417,7 @@ impl AckControlInfo {
 
         if let Some(info) = &self.info {
             total_size = ACK_MEDIUM_SIZE;
-            if buffer.len() < total_size {
-                return None;
-            }
+            check_size(buffer, total_size)?;
 
             // 2) 32 bits: RTT (in microseconds)
             buffer[4..8].copy_from_slice(&info.rtt.to_le_bytes());
@@ -155,35 +428,52 @@ impl AckControlInfo {
             // 4) 32 bits: Available buffer size (in bytes)
             buffer[12..16].copy_from_slice(&info.buffer_size.to_le_bytes());
 
-            if let Some((speed, bandwidth)) = &info.speed_and_bandwidth {
+            if let Some(rates) = &info.speed_and_bandwidth {
                 total_size = ACK_BIG_SIZE;
-                if buffer.len() < total_size {
-                    return None;
-                }
+                check_size(buffer, total_size)?;
 
                 // 5) 32 bits: Packets receiving rate (in number of packets per second)
-                buffer[16..20].copy_from_slice(&speed.to_le_bytes());
+                buffer[16..20].copy_from_slice(&rates.speed.to_le_bytes());
 
                 // 6) 32 bits: Estimated link capacity (in number of packets per second)
-                buffer[20..24].copy_from_slice(&bandwidth.to_le_bytes());
+                buffer[20..24].copy_from_slice(&rates.bandwidth.to_le_bytes());
+
+                if let Some((byte_speed, byte_bandwidth)) = &rates.bytes {
+                    total_size = ACK_FULL_SIZE;
+                    check_size(buffer, total_size)?;
+
+                    // 7) 32 bits: Receiving rate (in bytes per second)
+                    buffer[24..28].copy_from_slice(&byte_speed.to_le_bytes());
+
+                    // 8) 32 bits: Estimated link capacity (in bytes per second)
+                    buffer[28..32].copy_from_slice(&byte_bandwidth.to_le_bytes());
+                }
+            }
+
+            if let Some(one_way_delay) = info.one_way_delay {
+                let offset = total_size;
+                total_size += ACK_DELAY_SIZE;
+                check_size(buffer, total_size)?;
+
+                // Extension, 32 bits after the last field: One-way delay (in microseconds)
+                buffer[offset..total_size].copy_from_slice(&one_way_delay.to_le_bytes());
             }
         }
 
-        Some(buffer.split_at(total_size).0)
+        Ok(buffer.split_at(total_size).0)
     }
 
-    pub fn deserialize(buffer: &[u8]) -> Option<Self> {
-        if buffer.len() < ACK_SMALL_SIZE {
-            return None;
-        }
+    pub fn deserialize(buffer: &[u8]) -> Result<Self, PacketError> {
+        check_size(buffer, ACK_SMALL_SIZE)?;
 
         // 1) 32 bits: The packet sequence number to which all the previous packets
         //    have been received (excluding)
         let received_last_ack = u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]);
 
-        let info = if buffer.len() == ACK_SMALL_SIZE {
+        let (fields_size, has_delay) = ack_layout(buffer.len())?;
+        let info = if fields_size == ACK_SMALL_SIZE {
             None
-        } else if buffer.len() == ACK_MEDIUM_SIZE || buffer.len() == ACK_BIG_SIZE {
+        } else {
             // 2) 32 bits: RTT (in microseconds)
             let rtt = u32::from_le_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]);
 
@@ -193,35 +483,57 @@ impl AckControlInfo {
             // 4) 32 bits: Available buffer size (in bytes)
             let buffer_size = u32::from_le_bytes([buffer[12], buffer[13], buffer[14], buffer[15]]);
 
-            let speed_and_bandwidth = if buffer.len() == ACK_BIG_SIZE {
-                Some((
-                    // 5) 32 bits: Packets receiving rate (in number of packets per second)
-                    u32::from_le_bytes([buffer[16], buffer[17], buffer[18], buffer[19]]),
-                    // 6) 32 bits: Estimated link capacity (in number of packets per second)
-                    u32::from_le_bytes([buffer[20], buffer[21], buffer[22], buffer[23]]),
-                ))
+            let speed_and_bandwidth = if fields_size >= ACK_BIG_SIZE {
+                // 5) 32 bits: Packets receiving rate (in number of packets per second)
+                let speed = u32::from_le_bytes([buffer[16], buffer[17], buffer[18], buffer[19]]);
+
+                // 6) 32 bits: Estimated link capacity (in number of packets per second)
+                let bandwidth =
+                    u32::from_le_bytes([buffer[20], buffer[21], buffer[22], buffer[23]]);
+
+                let bytes = if fields_size >= ACK_FULL_SIZE {
+                    Some((
+                        // 7) 32 bits: Receiving rate (in bytes per second)
+                        u32::from_le_bytes([buffer[24], buffer[25], buffer[26], buffer[27]]),
+                        // 8) 32 bits: Estimated link capacity (in bytes per second)
+                        u32::from_le_bytes([buffer[28], buffer[29], buffer[30], buffer[31]]),
+                    ))
+                } else {
+                    None
+                };
+
+                Some(SpeedAndBandwidth {
+                    speed,
+                    bandwidth,
+                    bytes,
+                })
             } else {
                 None
             };
 
+            // Extension, 32 bits after the last field: One-way delay (in microseconds)
+            let one_way_delay = has_delay.then(|| {
+                let delay = &buffer[fields_size..fields_size + ACK_DELAY_SIZE];
+                u32::from_le_bytes([delay[0], delay[1], delay[2], delay[3]])
+            });
+
             Some(AckAdditionalInfo {
                 rtt,
                 rtt_var,
                 buffer_size,
                 speed_and_bandwidth,
+                one_way_delay,
             })
-        } else {
-            return None;
         };
 
-        Some(Self {
+        Ok(Self {
             received_last_ack,
             info,
         })
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct AckAdditionalInfo {
     /// RTT (in microseconds)
     pub rtt: u32,
@@ -229,14 +541,30 @@ pub struct AckAdditionalInfo {
     pub rtt_var: u32,
     /// Available buffer size (in bytes)
     pub buffer_size: u32,
+    /// Optional receiving rate and estimated link capacity
+    pub speed_and_bandwidth: Option<SpeedAndBandwidth>,
+    /// Optional one-way delay of the latest data packet (in microseconds), the arrival time
+    /// minus the packet timestamp. It includes the clock offset between the peers, so only
+    /// differences between samples are meaningful. Not part of standard UDT, it is appended
+    /// after the last present field.
+    pub one_way_delay: Option<u32>,
+}
+
+/// Receiving rate and estimated link capacity reported in ACK
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct SpeedAndBandwidth {
+    /// Packets receiving rate (in number of packets per second)
+    pub speed: u32,
+    /// Estimated link capacity (in number of packets per second)
+    pub bandwidth: u32,
     /// An optional tuple of:
-    /// - packets receiving rate (in number of packets per second)
-    /// - estimated link capacity (in number of packets per second)
-    pub speed_and_bandwidth: Option<(u32, u32)>,
+    /// - receiving rate (in bytes per second)
+    /// - estimated link capacity (in bytes per second)
+    pub bytes: Option<(u32, u32)>,
 }
 
 /// Handshake packet control info
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct HandshakeControlInfo {
     /// UDT socket type
     pub socket_type: SocketType,
@@ -257,10 +585,8 @@ pub struct HandshakeControlInfo {
 }
 
 impl HandshakeControlInfo {
-    pub fn serialize<'a>(&self, buffer: &'a mut [u8]) -> Option<&'a [u8]> {
-        if buffer.len() < HANDSHAKE_SIZE {
-            return None;
-        }
+    pub fn serialize<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a [u8], PacketError> {
+        check_size(buffer, HANDSHAKE_SIZE)?;
 
         // 1) 32 bits: UDT version
         buffer[0..4].copy_from_slice(&[UDT_VERSION, 0, 0, 0]);
@@ -301,24 +627,22 @@ impl HandshakeControlInfo {
         buffer[44..48].copy_from_slice(&self.ip[3].to_le_bytes());
 
         // Done
-        Some(buffer.split_at(HANDSHAKE_SIZE).0)
+        Ok(buffer.split_at(HANDSHAKE_SIZE).0)
     }
 
-    pub fn deserialize(buffer: &[u8]) -> Option<Self> {
-        if buffer.len() < HANDSHAKE_SIZE {
-            return None;
-        }
+    pub fn deserialize(buffer: &[u8]) -> Result<Self, PacketError> {
+        check_size(buffer, HANDSHAKE_SIZE)?;
 
         // 1) 32 bits: UDT version
         if buffer[0] != UDT_VERSION {
-            return None;
+            return Err(PacketError::UnsupportedVersion(buffer[0]));
         }
 
         // 2) 32 bits: Socket Type (STREAM or DGRAM)
         let socket_type = match buffer[4] {
             1 => SocketType::Stream,
             2 => SocketType::Datagram,
-            _ => return None,
+            ty => return Err(PacketError::InvalidSocketType(ty)),
         };
 
         // 3) 32 bits: initial packet sequence number
@@ -348,7 +672,7 @@ impl HandshakeControlInfo {
         ];
 
         // Done
-        Some(Self {
+        Ok(Self {
             socket_type,
             isn,
             mss,
@@ -359,6 +683,67 @@ impl HandshakeControlInfo {
             ip,
         })
     }
+
+    /// Stores the peer IP address.
+    ///
+    /// IPv4 address occupies the first word, IPv6 address occupies all four words.
+    /// Address octets are kept in network order on the wire.
+    pub fn set_peer_ip(&mut self, ip: IpAddr) {
+        self.ip = match ip {
+            IpAddr::V4(ip) => [u32::from_le_bytes(ip.octets()), 0, 0, 0],
+            IpAddr::V6(ip) => {
+                let octets = ip.octets();
+                let mut words = [0; 4];
+                for (word, chunk) in words.iter_mut().zip(octets.chunks_exact(4)) {
+                    *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+                }
+                words
+            }
+        };
+    }
+
+    /// Interprets the peer IP address as IPv4 (first word only)
+    pub fn peer_ipv4(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.ip[0].to_le_bytes())
+    }
+
+    /// Interprets the peer IP address as IPv6 (all four words)
+    pub fn peer_ipv6(&self) -> Ipv6Addr {
+        let mut octets = [0; 16];
+        for (chunk, word) in octets.chunks_exact_mut(4).zip(self.ip) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        Ipv6Addr::from(octets)
+    }
+
+    /// Returns the peer IP address in the same family as the observed address
+    pub fn peer_ip(&self, observed: IpAddr) -> IpAddr {
+        match observed {
+            IpAddr::V4(_) => IpAddr::V4(self.peer_ipv4()),
+            IpAddr::V6(_) => IpAddr::V6(self.peer_ipv6()),
+        }
+    }
+
+    /// Checks whether the reported peer IP address matches the observed source address.
+    ///
+    /// IPv4-mapped IPv6 addresses (as seen by dual-stack sockets) are compared as IPv4.
+    pub fn peer_ip_matches(&self, observed: IpAddr) -> bool {
+        let observed = match observed {
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(ip) => IpAddr::V4(ip),
+                None => IpAddr::V6(ip),
+            },
+            ip => ip,
+        };
+
+        match observed {
+            IpAddr::V4(ip) => {
+                let reported_v4 = self.ip[1..] == [0, 0, 0] && self.peer_ipv4() == ip;
+                reported_v4 || self.peer_ipv6().to_ipv4_mapped() == Some(ip)
+            }
+            IpAddr::V6(ip) => self.peer_ipv6() == ip,
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -367,14 +752,406 @@ pub enum SocketType {
     Datagram,
 }
 
-const UDT_VERSION: u8 = 4;
-const HANDSHAKE_SIZE: usize = 48;
+/// Splits an ACK control info size into the size of the standard fields and
+/// whether the one-way delay extension follows them
+pub(crate) fn ack_layout(size: usize) -> Result<(usize, bool), PacketError> {
+    match size {
+        ACK_SMALL_SIZE | ACK_MEDIUM_SIZE | ACK_BIG_SIZE | ACK_FULL_SIZE => Ok((size, false)),
+        _ => match size.checked_sub(ACK_DELAY_SIZE) {
+            Some(fields_size @ (ACK_MEDIUM_SIZE | ACK_BIG_SIZE | ACK_FULL_SIZE)) => {
+                Ok((fields_size, true))
+            }
+            _ => Err(PacketError::InvalidLength),
+        },
+    }
+}
+
+pub(crate) fn check_size(buffer: &[u8], needed: usize) -> Result<(), PacketError> {
+    if buffer.len() < needed {
+        Err(PacketError::BufferTooSmall {
+            needed,
+            got: buffer.len(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+pub(crate) const HEADER_SIZE: usize = 16;
+pub(crate) const CONTROL_FLAG: u32 = 1 << 31;
+const IN_ORDER_FLAG: u32 = 1 << 29;
+
+pub(crate) const UDT_VERSION: u8 = 4;
+pub(crate) const HANDSHAKE_SIZE: usize = 48;
+
+pub(crate) const ACK_SMALL_SIZE: usize = 4;
+pub(crate) const ACK_MEDIUM_SIZE: usize = ACK_SMALL_SIZE + 12;
+pub(crate) const ACK_BIG_SIZE: usize = ACK_MEDIUM_SIZE + 8;
+pub(crate) const ACK_FULL_SIZE: usize = ACK_BIG_SIZE + 8;
+/// Size of the one-way delay extension appended to ACK
+pub(crate) const ACK_DELAY_SIZE: usize = 4;
+
+/// Marks the first sequence number of a range in the compressed loss list
+pub(crate) const LOSS_RANGE_FLAG: u32 = 1 << 31;
+pub(crate) const NAK_SMALL_SIZE: usize = 4;
+pub(crate) const NAK_BIG_SIZE: usize = 8;
+
+pub(crate) const MDR_SIZE: usize = 8;
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::seq::MAX_SEQ_NO;
+
+    fn handshake() -> HandshakeControlInfo {
+        HandshakeControlInfo {
+            socket_type: SocketType::Stream,
+            isn: 123,
+            mss: 1500,
+            flight_flag_size: 25600,
+            request_type: 1,
+            id: 456,
+            cookie: 789,
+            ip: [0; 4],
+        }
+    }
+
+    #[test]
+    fn handshake_ip_round_trip() {
+        let mut buffer = [0; HANDSHAKE_SIZE];
+
+        for ip in [
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(Ipv6Addr::LOCALHOST),
+            "2001:db8::1".parse().unwrap(),
+        ] {
+            let mut info = handshake();
+            info.set_peer_ip(ip);
+
+            let data = info.serialize(&mut buffer).unwrap();
+            match ip {
+                IpAddr::V4(ip) => assert_eq!(&data[32..36], ip.octets()),
+                IpAddr::V6(ip) => assert_eq!(&data[32..48], ip.octets()),
+            }
+
+            let info = HandshakeControlInfo::deserialize(data).unwrap();
+            assert_eq!(info.peer_ip(ip), ip);
+            assert!(info.peer_ip_matches(ip));
+        }
+    }
+
+    #[test]
+    fn codec_errors() {
+        let mut buffer = [0; HANDSHAKE_SIZE];
+        let data = handshake().serialize(&mut buffer).unwrap().to_vec();
+
+        assert!(matches!(
+            handshake().serialize(&mut buffer[..10]),
+            Err(PacketError::BufferTooSmall {
+                needed: HANDSHAKE_SIZE,
+                got: 10
+            })
+        ));
+
+        let mut invalid = data.clone();
+        invalid[0] = 3;
+        assert!(matches!(
+            HandshakeControlInfo::deserialize(&invalid),
+            Err(PacketError::UnsupportedVersion(3))
+        ));
+
+        let mut invalid = data;
+        invalid[4] = 5;
+        assert!(matches!(
+            HandshakeControlInfo::deserialize(&invalid),
+            Err(PacketError::InvalidSocketType(5))
+        ));
+
+        assert!(matches!(
+            AckControlInfo::deserialize(&buffer[..ACK_MEDIUM_SIZE - 1]),
+            Err(PacketError::InvalidLength)
+        ));
+        assert!(matches!(
+            NakControlInfo::deserialize(&buffer[..6]),
+            Err(PacketError::InvalidLength)
+        ));
+        assert!(matches!(
+            PacketData::deserialize(8, 0, &[]),
+            Err(PacketError::UnknownControlType(8))
+        ));
+    }
+
+    #[test]
+    fn message_number_flags() {
+        let mut header = PacketHeader {
+            seq_no: 0,
+            msg_no: 0,
+            timestamp: 0,
+            id: 0,
+        };
+
+        header.set_message(MAX_MSG_NO, MessageBoundary::First, true);
+        assert_eq!(header.message_number(), MAX_MSG_NO);
+        assert_eq!(header.message_boundary(), MessageBoundary::First);
+        assert!(header.in_order());
+
+        header.set_message(42, MessageBoundary::Last, false);
+        assert_eq!(header.message_number(), 42);
+        assert_eq!(header.message_boundary(), MessageBoundary::Last);
+        assert!(!header.in_order());
+    }
+
+    #[test]
+    fn nak_ranges() {
+        let ranges = |info: NakControlInfo| info.ranges().collect::<Vec<_>>();
+
+        assert_eq!(ranges(NakControlInfo::range(5, 5)), [(5, 5)]);
+        assert_eq!(ranges(NakControlInfo::range(5, 9)), [(5, 9)]);
+        assert_eq!(
+            ranges(NakControlInfo::range(MAX_SEQ_NO, 1)),
+            [(MAX_SEQ_NO, 1)]
+        );
+        assert_eq!(
+            ranges(NakControlInfo::Multiple { loss_data: [3, 7] }),
+            [(3, 3), (7, 7)]
+        );
+    }
+
+    #[test]
+    fn handshake_ip_matches_dual_stack() {
+        let mut info = handshake();
+        info.set_peer_ip(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert!(info.peer_ip_matches(IpAddr::V6(Ipv4Addr::LOCALHOST.to_ipv6_mapped())));
+        assert!(!info.peer_ip_matches(IpAddr::V6(Ipv6Addr::LOCALHOST)));
+        assert!(!info.peer_ip_matches(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2))));
+
+        info.set_peer_ip(IpAddr::V6(Ipv4Addr::LOCALHOST.to_ipv6_mapped()));
+        assert!(info.peer_ip_matches(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+
+        info.set_peer_ip(IpAddr::V6(Ipv6Addr::LOCALHOST));
+        assert!(!info.peer_ip_matches(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+    }
+
+    #[test]
+    fn ack_seq_no_round_trip() {
+        use std::time::{Duration, Instant};
+
+        use crate::window::AckWindow;
+
+        let mut window = AckWindow::new(16).unwrap();
+        let mut buffer = [0; HEADER_SIZE + ACK_FULL_SIZE];
+
+        // Receiver sends ACK 5 acknowledging data up to 1000
+        let sent = Instant::now();
+        window.store(5, 1000, sent);
+        let ack = ControlPacket {
+            timestamp: 1,
+            id: 42,
+            data: PacketData::Ack {
+                ack_seq_no: 5,
+                info: AckControlInfo {
+                    received_last_ack: 1000,
+                    info: None,
+                },
+            },
+        };
+        let ack = ControlPacket::deserialize(ack.serialize(&mut buffer).unwrap()).unwrap();
+        let PacketData::Ack { ack_seq_no, .. } = ack.data else {
+            panic!("ACK expected");
+        };
+
+        // Sender echoes the ACK sequence number in ACK-2
+        let ack2 = ControlPacket {
+            timestamp: 2,
+            id: 42,
+            data: PacketData::Ack2 { ack_seq_no },
+        };
+        let ack2 = ControlPacket::deserialize(ack2.serialize(&mut buffer).unwrap()).unwrap();
+        let PacketData::Ack2 { ack_seq_no } = ack2.data else {
+            panic!("ACK-2 expected");
+        };
+
+        let ack = window
+            .acknowledge(ack_seq_no, sent + Duration::from_millis(30))
+            .unwrap();
+        assert_eq!(ack.data_seq_no, 1000);
+        assert_eq!(ack.rtt, Duration::from_millis(30));
+
+        let header = PacketHeader {
+            seq_no: 1,
+            msg_no: 2,
+            timestamp: 3,
+            id: 4,
+        };
+        header.serialize(&mut buffer).unwrap();
+        assert!(matches!(
+            ControlPacket::deserialize(&buffer),
+            Err(PacketError::UnexpectedDataPacket)
+        ));
+    }
+
+    fn socket_type() -> impl Strategy<Value = SocketType> {
+        prop_oneof![Just(SocketType::Stream), Just(SocketType::Datagram)]
+    }
+
+    fn handshake_info() -> impl Strategy<Value = HandshakeControlInfo> {
+        (
+            socket_type(),
+            any::<u32>(),
+            any::<u32>(),
+            any::<u32>(),
+            any::<i32>(),
+            any::<u32>(),
+            any::<u32>(),
+            any::<[u32; 4]>(),
+        )
+            .prop_map(
+                |(socket_type, isn, mss, flight_flag_size, request_type, id, cookie, ip)| {
+                    HandshakeControlInfo {
+                        socket_type,
+                        isn,
+                        mss,
+                        flight_flag_size,
+                        request_type,
+                        id,
+                        cookie,
+                        ip,
+                    }
+                },
+            )
+    }
 
-const ACK_SMALL_SIZE: usize = 4;
-const ACK_MEDIUM_SIZE: usize = ACK_SMALL_SIZE + 12;
-const ACK_BIG_SIZE: usize = ACK_MEDIUM_SIZE + 8;
+    fn ack_info() -> impl Strategy<Value = AckControlInfo> {
+        let info = (
+            any::<u32>(),
+            any::<u32>(),
+            any::<u32>(),
+            proptest::option::of(
+                (
+                    any::<u32>(),
+                    any::<u32>(),
+                    proptest::option::of(any::<(u32, u32)>()),
+                )
+                    .prop_map(|(speed, bandwidth, bytes)| SpeedAndBandwidth {
+                        speed,
+                        bandwidth,
+                        bytes,
+                    }),
+            ),
+            proptest::option::of(any::<u32>()),
+        )
+            .prop_map(
+                |(rtt, rtt_var, buffer_size, speed_and_bandwidth, one_way_delay)| {
+                    AckAdditionalInfo {
+                        rtt,
+                        rtt_var,
+                        buffer_size,
+                        speed_and_bandwidth,
+                        one_way_delay,
+                    }
+                },
+            );
 
-const NAK_SMALL_SIZE: usize = 4;
-const NAK_BIG_SIZE: usize = 8;
+        (any::<u32>(), proptest::option::of(info)).prop_map(|(received_last_ack, info)| {
+            AckControlInfo {
+                received_last_ack,
+                info,
+            }
+        })
+    }
 
-const MDR_SIZE: usize = 8;
+    fn nak_info() -> impl Strategy<Value = NakControlInfo> {
+        prop_oneof![
+            any::<u32>().prop_map(|seq_no| NakControlInfo::Single { seq_no }),
+            any::<[u32; 2]>().prop_map(|loss_data| NakControlInfo::Multiple { loss_data }),
+        ]
+    }
+
+    fn mdr_info() -> impl Strategy<Value = MessageDropRequestControlInfo> {
+        any::<(u32, u32)>()
+            .prop_map(|(first, last)| MessageDropRequestControlInfo::new(first, last))
+    }
+
+    fn packet_data() -> impl Strategy<Value = PacketData> {
+        prop_oneof![
+            handshake_info().prop_map(PacketData::Handshake),
+            Just(PacketData::KeepAlive),
+            (any::<u32>(), ack_info())
+                .prop_map(|(ack_seq_no, info)| PacketData::Ack { ack_seq_no, info }),
+            nak_info().prop_map(PacketData::Nak),
+            Just(PacketData::CongestionWarning),
+            Just(PacketData::Shutdown),
+            any::<u32>().prop_map(|ack_seq_no| PacketData::Ack2 { ack_seq_no }),
+            (any::<u32>(), mdr_info())
+                .prop_map(|(msg_no, info)| PacketData::MessageDropRequest { msg_no, info }),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn header_round_trip(
+            seq_no in 0..=MAX_SEQ_NO,
+            msg_no in any::<u32>(),
+            timestamp in any::<u32>(),
+            id in any::<u32>(),
+        ) {
+            let header = PacketHeader { seq_no, msg_no, timestamp, id };
+            let mut buffer = [0; HEADER_SIZE];
+            let data = header.serialize(&mut buffer).unwrap();
+            prop_assert_eq!(PacketHeader::deserialize(data).unwrap(), header);
+        }
+
+        #[test]
+        fn handshake_round_trip(info in handshake_info()) {
+            let mut buffer = [0; 64];
+            let data = info.serialize(&mut buffer).unwrap();
+            prop_assert_eq!(HandshakeControlInfo::deserialize(data).unwrap(), info);
+        }
+
+        #[test]
+        fn ack_round_trip(info in ack_info()) {
+            let mut buffer = [0; 64];
+            let data = info.serialize(&mut buffer).unwrap();
+            prop_assert_eq!(AckControlInfo::deserialize(data).unwrap(), info);
+        }
+
+        #[test]
+        fn nak_round_trip(info in nak_info()) {
+            let mut buffer = [0; 64];
+            let data = info.serialize(&mut buffer).unwrap();
+            prop_assert_eq!(NakControlInfo::deserialize(data).unwrap(), info);
+        }
+
+        #[test]
+        fn mdr_round_trip(info in mdr_info()) {
+            let mut buffer = [0; 64];
+            let data = info.serialize(&mut buffer).unwrap();
+            prop_assert_eq!(MessageDropRequestControlInfo::deserialize(data).unwrap(), info);
+        }
+
+        #[test]
+        fn control_packet_round_trip(
+            timestamp in any::<u32>(),
+            id in any::<u32>(),
+            data in packet_data(),
+        ) {
+            let packet = ControlPacket { timestamp, id, data };
+            let mut buffer = [0; HEADER_SIZE + 64];
+            let serialized = packet.serialize(&mut buffer).unwrap();
+            prop_assert_eq!(ControlPacket::deserialize(serialized).unwrap(), packet);
+        }
+
+        #[test]
+        fn deserialize_never_panics(
+            control_type in 0u16..10,
+            data in proptest::collection::vec(any::<u8>(), 0..64),
+        ) {
+            let _ = PacketHeader::deserialize(&data);
+            let _ = ControlPacket::deserialize(&data);
+            let _ = PacketData::deserialize(control_type, 0, &data);
+            let _ = crate::packet_ref::PacketRef::parse(&data);
+        }
+    }
+}
@@ -0,0 +1,498 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::error::PacketError;
+use crate::packet::*;
+
+/// Borrowed view of a received datagram.
+///
+/// The packet is validated once in [`PacketRef::parse`],
+/// all fields are read lazily from the underlying buffer.
+#[derive(Debug, Copy, Clone)]
+pub enum PacketRef<'a> {
+    Data(DataPacketRef<'a>),
+    Control(ControlPacketRef<'a>),
+}
+
+impl<'a> PacketRef<'a> {
+    pub fn parse(buffer: &'a [u8]) -> Result<Self, PacketError> {
+        check_size(buffer, HEADER_SIZE)?;
+
+        if read_u32(buffer, 0) & CONTROL_FLAG == 0 {
+            return Ok(Self::Data(DataPacketRef { buffer }));
+        }
+
+        let packet = ControlPacketRef { buffer };
+        let body = packet.body();
+        match packet.control_type() {
+            0 => {
+                HandshakeRef::new(body)?;
+            }
+            2 => {
+                AckRef::new(body)?;
+            }
+            3 => {
+                NakRef::new(body)?;
+            }
+            7 => {
+                MessageDropRequestRef::new(body)?;
+            }
+            1 | 4 | 5 | 6 => {}
+            ty => return Err(PacketError::UnknownControlType(ty)),
+        }
+
+        Ok(Self::Control(packet))
+    }
+
+    /// Packet timestamp
+    pub fn timestamp(&self) -> u32 {
+        match self {
+            Self::Data(packet) => packet.timestamp(),
+            Self::Control(packet) => packet.timestamp(),
+        }
+    }
+
+    /// Destination socket ID
+    pub fn id(&self) -> u32 {
+        match self {
+            Self::Data(packet) => packet.id(),
+            Self::Control(packet) => packet.id(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct DataPacketRef<'a> {
+    buffer: &'a [u8],
+}
+
+impl<'a> DataPacketRef<'a> {
+    /// Packet sequence number
+    pub fn seq_no(&self) -> u32 {
+        read_u32(self.buffer, 0)
+    }
+
+    /// Message number with boundary and order flags
+    pub fn msg_no(&self) -> u32 {
+        read_u32(self.buffer, 4)
+    }
+
+    /// Packet timestamp
+    pub fn timestamp(&self) -> u32 {
+        read_u32(self.buffer, 8)
+    }
+
+    /// Destination socket ID
+    pub fn id(&self) -> u32 {
+        read_u32(self.buffer, 12)
+    }
+
+    pub fn header(&self) -> PacketHeader {
+        PacketHeader {
+            seq_no: self.seq_no(),
+            msg_no: self.msg_no(),
+            timestamp: self.timestamp(),
+            id: self.id(),
+        }
+    }
+
+    /// Packet payload
+    pub fn payload(&self) -> &'a [u8] {
+        &self.buffer[HEADER_SIZE..]
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ControlPacketRef<'a> {
+    buffer: &'a [u8],
+}
+
+impl<'a> ControlPacketRef<'a> {
+    /// Control packet type
+    pub fn control_type(&self) -> u16 {
+        ((read_u32(self.buffer, 0) & !CONTROL_FLAG) >> 16) as u16
+    }
+
+    /// Additional info (type specific)
+    pub fn additional_info(&self) -> u32 {
+        read_u32(self.buffer, 4)
+    }
+
+    /// Packet timestamp
+    pub fn timestamp(&self) -> u32 {
+        read_u32(self.buffer, 8)
+    }
+
+    /// Destination socket ID
+    pub fn id(&self) -> u32 {
+        read_u32(self.buffer, 12)
+    }
+
+    /// Raw control info
+    pub fn body(&self) -> &'a [u8] {
+        &self.buffer[HEADER_SIZE..]
+    }
+
+    /// Typed view of the control info
+    pub fn data(&self) -> PacketDataRef<'a> {
+        let buffer = self.body();
+        match self.control_type() {
+            0 => PacketDataRef::Handshake(HandshakeRef { buffer }),
+            1 => PacketDataRef::KeepAlive,
+            2 => PacketDataRef::Ack {
+                ack_seq_no: self.additional_info(),
+                info: AckRef { buffer },
+            },
+            3 => PacketDataRef::Nak(NakRef { buffer }),
+            4 => PacketDataRef::CongestionWarning,
+            5 => PacketDataRef::Shutdown,
+            6 => PacketDataRef::Ack2 {
+                ack_seq_no: self.additional_info(),
+            },
+            // NOTE: control type is validated in `PacketRef::parse`
+            _ => PacketDataRef::MessageDropRequest {
+                msg_no: self.additional_info(),
+                info: MessageDropRequestRef { buffer },
+            },
+        }
+    }
+}
+
+/// Borrowed counterpart of [`PacketData`]
+#[derive(Debug, Copy, Clone)]
+pub enum PacketDataRef<'a> {
+    Handshake(HandshakeRef<'a>),
+    KeepAlive,
+    Ack {
+        ack_seq_no: u32,
+        info: AckRef<'a>,
+    },
+    Nak(NakRef<'a>),
+    CongestionWarning,
+    Shutdown,
+    Ack2 {
+        ack_seq_no: u32,
+    },
+    MessageDropRequest {
+        msg_no: u32,
+        info: MessageDropRequestRef<'a>,
+    },
+}
+
+impl PacketDataRef<'_> {
+    pub fn to_owned(&self) -> PacketData {
+        match self {
+            Self::Handshake(info) => PacketData::Handshake(info.to_owned()),
+            Self::KeepAlive => PacketData::KeepAlive,
+            Self::Ack { ack_seq_no, info } => PacketData::Ack {
+                ack_seq_no: *ack_seq_no,
+                info: info.to_owned(),
+            },
+            Self::Nak(info) => PacketData::Nak(info.to_owned()),
+            Self::CongestionWarning => PacketData::CongestionWarning,
+            Self::Shutdown => PacketData::Shutdown,
+            Self::Ack2 { ack_seq_no } => PacketData::Ack2 {
+                ack_seq_no: *ack_seq_no,
+            },
+            Self::MessageDropRequest { msg_no, info } => PacketData::MessageDropRequest {
+                msg_no: *msg_no,
+                info: info.to_owned(),
+            },
+        }
+    }
+}
+
+/// Borrowed view of [`HandshakeControlInfo`]
+#[derive(Debug, Copy, Clone)]
+pub struct HandshakeRef<'a> {
+    buffer: &'a [u8],
+}
+
+impl<'a> HandshakeRef<'a> {
+    pub fn new(buffer: &'a [u8]) -> Result<Self, PacketError> {
+        check_size(buffer, HANDSHAKE_SIZE)?;
+        if buffer[0] != UDT_VERSION {
+            return Err(PacketError::UnsupportedVersion(buffer[0]));
+        }
+        if !matches!(buffer[4], 1 | 2) {
+            return Err(PacketError::InvalidSocketType(buffer[4]));
+        }
+        Ok(Self { buffer })
+    }
+
+    pub fn socket_type(&self) -> SocketType {
+        match self.buffer[4] {
+            1 => SocketType::Stream,
+            _ => SocketType::Datagram,
+        }
+    }
+
+    pub fn isn(&self) -> u32 {
+        read_u32(self.buffer, 8)
+    }
+
+    pub fn mss(&self) -> u32 {
+        read_u32(self.buffer, 12)
+    }
+
+    pub fn flight_flag_size(&self) -> u32 {
+        read_u32(self.buffer, 16)
+    }
+
+    pub fn request_type(&self) -> i32 {
+        read_u32(self.buffer, 20) as i32
+    }
+
+    pub fn id(&self) -> u32 {
+        read_u32(self.buffer, 24)
+    }
+
+    pub fn cookie(&self) -> u32 {
+        read_u32(self.buffer, 28)
+    }
+
+    pub fn ip(&self) -> [u32; 4] {
+        [
+            read_u32(self.buffer, 32),
+            read_u32(self.buffer, 36),
+            read_u32(self.buffer, 40),
+            read_u32(self.buffer, 44),
+        ]
+    }
+
+    pub fn peer_ipv4(&self) -> Ipv4Addr {
+        Ipv4Addr::new(
+            self.buffer[32],
+            self.buffer[33],
+            self.buffer[34],
+            self.buffer[35],
+        )
+    }
+
+    pub fn peer_ipv6(&self) -> Ipv6Addr {
+        let mut octets = [0; 16];
+        octets.copy_from_slice(&self.buffer[32..48]);
+        Ipv6Addr::from(octets)
+    }
+
+    pub fn to_owned(&self) -> HandshakeControlInfo {
+        HandshakeControlInfo {
+            socket_type: self.socket_type(),
+            isn: self.isn(),
+            mss: self.mss(),
+            flight_flag_size: self.flight_flag_size(),
+            request_type: self.request_type(),
+            id: self.id(),
+            cookie: self.cookie(),
+            ip: self.ip(),
+        }
+    }
+}
+
+/// Borrowed view of [`AckControlInfo`]
+#[derive(Debug, Copy, Clone)]
+pub struct AckRef<'a> {
+    buffer: &'a [u8],
+}
+
+impl<'a> AckRef<'a> {
+    pub fn new(buffer: &'a [u8]) -> Result<Self, PacketError> {
+        check_size(buffer, ACK_SMALL_SIZE)?;
+        ack_layout(buffer.len())?;
+        Ok(Self { buffer })
+    }
+
+    pub fn received_last_ack(&self) -> u32 {
+        read_u32(self.buffer, 0)
+    }
+
+    pub fn info(&self) -> Option<AckAdditionalInfo> {
+        let (fields_size, has_delay) = ack_layout(self.buffer.len()).ok()?;
+        if fields_size == ACK_SMALL_SIZE {
+            return None;
+        }
+
+        Some(AckAdditionalInfo {
+            rtt: read_u32(self.buffer, 4),
+            rtt_var: read_u32(self.buffer, 8),
+            buffer_size: read_u32(self.buffer, 12),
+            speed_and_bandwidth: (fields_size >= ACK_BIG_SIZE).then(|| SpeedAndBandwidth {
+                speed: read_u32(self.buffer, 16),
+                bandwidth: read_u32(self.buffer, 20),
+                bytes: (fields_size >= ACK_FULL_SIZE)
+                    .then(|| (read_u32(self.buffer, 24), read_u32(self.buffer, 28))),
+            }),
+            one_way_delay: has_delay.then(|| read_u32(self.buffer, fields_size)),
+        })
+    }
+
+    pub fn to_owned(&self) -> AckControlInfo {
+        AckControlInfo {
+            received_last_ack: self.received_last_ack(),
+            info: self.info(),
+        }
+    }
+}
+
+/// Borrowed view of [`NakControlInfo`]
+#[derive(Debug, Copy, Clone)]
+pub struct NakRef<'a> {
+    buffer: &'a [u8],
+}
+
+impl<'a> NakRef<'a> {
+    pub fn new(buffer: &'a [u8]) -> Result<Self, PacketError> {
+        check_size(buffer, NAK_SMALL_SIZE)?;
+        if !matches!(buffer.len(), NAK_SMALL_SIZE | NAK_BIG_SIZE) {
+            return Err(PacketError::InvalidLength);
+        }
+        Ok(Self { buffer })
+    }
+
+    pub fn to_owned(&self) -> NakControlInfo {
+        if self.buffer.len() == NAK_BIG_SIZE {
+            NakControlInfo::Multiple {
+                loss_data: [read_u32(self.buffer, 0), read_u32(self.buffer, 4)],
+            }
+        } else {
+            NakControlInfo::Single {
+                seq_no: read_u32(self.buffer, 0),
+            }
+        }
+    }
+}
+
+/// Borrowed view of [`MessageDropRequestControlInfo`]
+#[derive(Debug, Copy, Clone)]
+pub struct MessageDropRequestRef<'a> {
+    buffer: &'a [u8],
+}
+
+impl<'a> MessageDropRequestRef<'a> {
+    pub fn new(buffer: &'a [u8]) -> Result<Self, PacketError> {
+        check_size(buffer, MDR_SIZE)?;
+        if buffer.len() != MDR_SIZE {
+            return Err(PacketError::InvalidLength);
+        }
+        Ok(Self { buffer })
+    }
+
+    pub fn first_seq_no(&self) -> u32 {
+        read_u32(self.buffer, 0)
+    }
+
+    pub fn last_seq_no(&self) -> u32 {
+        read_u32(self.buffer, 4)
+    }
+
+    pub fn to_owned(&self) -> MessageDropRequestControlInfo {
+        MessageDropRequestControlInfo::new(self.first_seq_no(), self.last_seq_no())
+    }
+}
+
+#[inline(always)]
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buffer[offset],
+        buffer[offset + 1],
+        buffer[offset + 2],
+        buffer[offset + 3],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn control_packet(data: PacketData, buffer: &mut [u8]) -> usize {
+        let packet = ControlPacket {
+            timestamp: 100,
+            id: 42,
+            data,
+        };
+        packet.serialize(buffer).unwrap().len()
+    }
+
+    #[test]
+    fn data_packet_view() {
+        let header = PacketHeader {
+            seq_no: 123,
+            msg_no: 456,
+            timestamp: 789,
+            id: 42,
+        };
+
+        let mut buffer = [0; HEADER_SIZE + 4];
+        header.serialize(&mut buffer).unwrap();
+        buffer[HEADER_SIZE..].copy_from_slice(b"test");
+
+        let PacketRef::Data(packet) = PacketRef::parse(&buffer).unwrap() else {
+            panic!("data packet expected");
+        };
+        assert_eq!(packet.seq_no(), 123);
+        assert_eq!(packet.header().msg_no, 456);
+        assert_eq!(packet.timestamp(), 789);
+        assert_eq!(packet.id(), 42);
+        assert_eq!(packet.payload(), b"test");
+    }
+
+    #[test]
+    fn control_packet_view() {
+        let mut buffer = [0; 64];
+
+        let ack = PacketData::Ack {
+            ack_seq_no: 7,
+            info: AckControlInfo {
+                received_last_ack: 10,
+                info: Some(AckAdditionalInfo {
+                    rtt: 100,
+                    rtt_var: 50,
+                    buffer_size: 8192,
+                    speed_and_bandwidth: Some(SpeedAndBandwidth {
+                        speed: 1000,
+                        bandwidth: 2000,
+                        bytes: Some((1_456_000, 2_912_000)),
+                    }),
+                    one_way_delay: Some(20_000),
+                }),
+            },
+        };
+        let len = control_packet(ack, &mut buffer);
+
+        let PacketRef::Control(packet) = PacketRef::parse(&buffer[..len]).unwrap() else {
+            panic!("control packet expected");
+        };
+        assert_eq!(packet.control_type(), 2);
+        assert_eq!(packet.additional_info(), 7);
+        assert_eq!(packet.timestamp(), 100);
+        assert_eq!(packet.id(), 42);
+
+        let PacketDataRef::Ack { ack_seq_no, info } = packet.data() else {
+            panic!("ACK expected");
+        };
+        assert_eq!(ack_seq_no, 7);
+        assert_eq!(info.received_last_ack(), 10);
+        let additional = info.info().unwrap();
+        let rates = additional.speed_and_bandwidth.unwrap();
+        assert_eq!((rates.speed, rates.bandwidth), (1000, 2000));
+        assert_eq!(rates.bytes, Some((1_456_000, 2_912_000)));
+        assert_eq!(additional.one_way_delay, Some(20_000));
+
+        let len = control_packet(PacketData::Shutdown, &mut buffer);
+        let PacketRef::Control(packet) = PacketRef::parse(&buffer[..len]).unwrap() else {
+            panic!("control packet expected");
+        };
+        assert!(matches!(packet.data(), PacketDataRef::Shutdown));
+
+        let len = control_packet(ack, &mut buffer);
+        assert!(matches!(
+            PacketRef::parse(&buffer[..len - 1]),
+            Err(PacketError::InvalidLength)
+        ));
+
+        buffer[2] = 8;
+        assert!(matches!(
+            PacketRef::parse(&buffer[..len]),
+            Err(PacketError::UnknownControlType(8))
+        ));
+    }
+}
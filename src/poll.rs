@@ -0,0 +1,278 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::ops::BitOr;
+use std::time::{Duration, Instant};
+
+use crate::endpoint::{Endpoint, SocketId};
+use crate::transport::Transport;
+
+/// Readiness a poll target is registered for
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Interest(u8);
+
+impl Interest {
+    /// Data to read, a connection to accept or the end of the stream
+    pub const READ: Interest = Interest(1);
+    /// Space in the send buffer
+    pub const WRITE: Interest = Interest(2);
+    /// Broken or failed connection
+    pub const ERROR: Interest = Interest(4);
+
+    pub fn contains(self, other: Interest) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Interest {
+    type Output = Interest;
+
+    fn bitor(self, rhs: Interest) -> Interest {
+        Interest(self.0 | rhs.0)
+    }
+}
+
+/// Socket or listener of an [`Endpoint`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Target {
+    /// Readable when a connection can be accepted
+    Listener,
+    Socket(SocketId),
+}
+
+/// Readiness of a registered target, only the registered interest is reported
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub target: Target,
+    pub readable: bool,
+    pub writable: bool,
+    pub error: bool,
+}
+
+/// Waits for the readiness of many sockets of one endpoint, like `epoll` of UDT.
+///
+/// Readiness is level-triggered: a target is reported by every [`UdtPoll::wait`] while it is
+/// ready. A connecting socket becomes writable once the connection is established. Sockets
+/// which failed to connect, broke or were closed report an error.
+#[derive(Debug, Default)]
+pub struct UdtPoll {
+    targets: BTreeMap<Target, Interest>,
+}
+
+impl UdtPoll {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `target`, returns [`io::ErrorKind::AlreadyExists`] if it is registered
+    pub fn add(&mut self, target: Target, interest: Interest) -> io::Result<()> {
+        if self.targets.contains_key(&target) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        self.targets.insert(target, interest);
+        Ok(())
+    }
+
+    /// Changes the interest of a registered target
+    pub fn update(&mut self, target: Target, interest: Interest) -> io::Result<()> {
+        let registered = self
+            .targets
+            .get_mut(&target)
+            .ok_or(io::ErrorKind::NotFound)?;
+        *registered = interest;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, target: Target) -> io::Result<()> {
+        self.targets
+            .remove(&target)
+            .map(|_| ())
+            .ok_or_else(|| io::ErrorKind::NotFound.into())
+    }
+
+    /// Drives `endpoint` until a registered target is ready or `timeout` expires.
+    ///
+    /// `events` is replaced by the ready targets, returns their number (zero on timeout).
+    /// Without a timeout it blocks until a target is ready. Returns
+    /// [`io::ErrorKind::Unsupported`] if the transport can't block and nothing is ready.
+    pub fn wait<T: Transport>(
+        &self,
+        endpoint: &mut Endpoint<T>,
+        events: &mut Vec<Event>,
+        timeout: Option<Duration>,
+    ) -> io::Result<usize> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            endpoint.drive(Instant::now())?;
+
+            events.clear();
+            events.extend(
+                self.targets
+                    .iter()
+                    .filter_map(|(&target, &interest)| readiness(endpoint, target, interest)),
+            );
+            if !events.is_empty() || deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(events.len());
+            }
+
+            endpoint.wait(deadline)?;
+        }
+    }
+}
+
+fn readiness<T: Transport>(
+    endpoint: &Endpoint<T>,
+    target: Target,
+    interest: Interest,
+) -> Option<Event> {
+    let (readable, writable, error) = match target {
+        Target::Listener => (endpoint.is_acceptable(), false, false),
+        Target::Socket(id) => match endpoint.is_connected(id) {
+            Ok(false) => (false, false, false),
+            Ok(true) => match endpoint.connection(id) {
+                Ok(conn) => (conn.is_readable(), conn.is_writable(), conn.is_broken()),
+                Err(_) => (false, false, true),
+            },
+            Err(_) => (false, false, true),
+        },
+    };
+
+    let event = Event {
+        target,
+        readable: readable && interest.contains(Interest::READ),
+        writable: writable && interest.contains(Interest::WRITE),
+        error: error && interest.contains(Interest::ERROR),
+    };
+    (event.readable || event.writable || event.error).then_some(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+
+    use super::*;
+
+    fn endpoint() -> Endpoint<UdpSocket> {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        Endpoint::new(socket, Default::default()).unwrap()
+    }
+
+    #[test]
+    fn interest() {
+        let interest = Interest::READ | Interest::ERROR;
+        assert!(interest.contains(Interest::READ));
+        assert!(interest.contains(Interest::ERROR));
+        assert!(!interest.contains(Interest::WRITE));
+        assert!(!interest.contains(Interest::READ | Interest::WRITE));
+    }
+
+    #[test]
+    fn registration() {
+        let mut poll = UdtPoll::new();
+        poll.add(Target::Socket(1), Interest::READ).unwrap();
+        assert_eq!(
+            poll.add(Target::Socket(1), Interest::WRITE)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        poll.update(Target::Socket(1), Interest::WRITE).unwrap();
+        poll.remove(Target::Socket(1)).unwrap();
+        assert_eq!(
+            poll.update(Target::Socket(1), Interest::READ)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+        assert_eq!(
+            poll.remove(Target::Socket(1)).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn accept_read_and_write() {
+        let timeout = Some(Duration::from_millis(20));
+        let mut events = Vec::new();
+
+        let mut server = endpoint();
+        server.listen(1);
+        let mut server_poll = UdtPoll::new();
+        server_poll.add(Target::Listener, Interest::READ).unwrap();
+        assert_eq!(
+            server_poll.wait(&mut server, &mut events, timeout).unwrap(),
+            0
+        );
+
+        let mut client = endpoint();
+        let client_id = client
+            .connect(server.local_addr().unwrap(), Instant::now())
+            .unwrap();
+        let mut client_poll = UdtPoll::new();
+        client_poll
+            .add(Target::Socket(client_id), Interest::WRITE | Interest::ERROR)
+            .unwrap();
+
+        // The client is writable once the handshake completes
+        while client_poll.wait(&mut client, &mut events, timeout).unwrap() == 0 {
+            server_poll.wait(&mut server, &mut events, timeout).unwrap();
+        }
+        assert_eq!(
+            events,
+            [Event {
+                target: Target::Socket(client_id),
+                readable: false,
+                writable: true,
+                error: false,
+            }]
+        );
+
+        while server_poll.wait(&mut server, &mut events, timeout).unwrap() == 0 {}
+        assert_eq!(events[0].target, Target::Listener);
+        assert!(events[0].readable);
+        let (server_id, _) = server.accept().unwrap();
+        server_poll.remove(Target::Listener).unwrap();
+        server_poll
+            .add(Target::Socket(server_id), Interest::READ)
+            .unwrap();
+        assert_eq!(
+            server_poll.wait(&mut server, &mut events, timeout).unwrap(),
+            0
+        );
+
+        client.send(client_id, b"data").unwrap();
+        while server_poll.wait(&mut server, &mut events, timeout).unwrap() == 0 {
+            client.drive(Instant::now()).unwrap();
+        }
+        assert_eq!(
+            events,
+            [Event {
+                target: Target::Socket(server_id),
+                readable: true,
+                writable: false,
+                error: false,
+            }]
+        );
+        let mut buffer = [0; 16];
+        assert_eq!(server.recv(server_id, &mut buffer).unwrap(), 4);
+        assert_eq!(&buffer[..4], b"data");
+    }
+
+    #[test]
+    fn rejected_connection_reports_error() {
+        let mut server = endpoint();
+        let mut client = endpoint();
+        let id = client
+            .connect(server.local_addr().unwrap(), Instant::now())
+            .unwrap();
+
+        let mut poll = UdtPoll::new();
+        poll.add(Target::Socket(id), Interest::ERROR).unwrap();
+        let mut events = Vec::new();
+        server
+            .wait(Some(Instant::now() + Duration::from_secs(1)))
+            .unwrap();
+        server.drive(Instant::now()).unwrap();
+        assert_eq!(poll.wait(&mut client, &mut events, None).unwrap(), 1);
+        assert!(events[0].error);
+    }
+}
@@ -0,0 +1,449 @@
+use std::collections::VecDeque;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::seq::{seq_add, seq_cmp, seq_inc, seq_offset};
+use crate::transport::Transport;
+
+/// Lock-free pool of fixed-size packet buffers.
+///
+/// Each slot either holds a free buffer or is empty, buffers are moved in and out
+/// of slots with a single atomic swap, so there is no ABA problem.
+#[derive(Clone)]
+pub struct BufferPool {
+    inner: Arc<PoolInner>,
+}
+
+impl BufferPool {
+    /// Creates a pool of `capacity` buffers of `buffer_size` bytes (usually the negotiated MSS)
+    pub fn new(buffer_size: usize, capacity: usize) -> Self {
+        let inner = Arc::new(PoolInner {
+            buffer_size,
+            slots: (0..capacity)
+                .map(|_| AtomicPtr::new(std::ptr::null_mut()))
+                .collect(),
+            hint: AtomicUsize::new(0),
+        });
+
+        for slot in inner.slots.iter() {
+            slot.store(alloc(buffer_size).as_ptr(), Ordering::Relaxed);
+        }
+
+        Self { inner }
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.inner.buffer_size
+    }
+
+    /// Number of free buffers
+    pub fn available(&self) -> usize {
+        self.inner
+            .slots
+            .iter()
+            .filter(|slot| !slot.load(Ordering::Relaxed).is_null())
+            .count()
+    }
+
+    /// Takes a free buffer from the pool or allocates a new one if the pool is empty
+    pub fn acquire(&self) -> PacketBuffer {
+        let inner = &self.inner;
+        let data = inner.take().unwrap_or_else(|| alloc(inner.buffer_size));
+
+        PacketBuffer {
+            data,
+            len: 0,
+            pool: self.inner.clone(),
+        }
+    }
+}
+
+impl std::fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("buffer_size", &self.inner.buffer_size)
+            .field("capacity", &self.inner.slots.len())
+            .field("available", &self.available())
+            .finish()
+    }
+}
+
+struct PoolInner {
+    buffer_size: usize,
+    slots: Box<[AtomicPtr<u8>]>,
+    /// Slot index to start the search from
+    hint: AtomicUsize,
+}
+
+impl PoolInner {
+    fn take(&self) -> Option<NonNull<u8>> {
+        let start = self.hint.load(Ordering::Relaxed);
+        let len = self.slots.len();
+        for i in 0..len {
+            let index = (start + i) % len;
+            let ptr = self.slots[index].swap(std::ptr::null_mut(), Ordering::Acquire);
+            if let Some(ptr) = NonNull::new(ptr) {
+                self.hint.store(index, Ordering::Relaxed);
+                return Some(ptr);
+            }
+        }
+        None
+    }
+
+    fn put(&self, ptr: NonNull<u8>) {
+        let start = self.hint.load(Ordering::Relaxed);
+        let len = self.slots.len();
+        for i in 0..len {
+            let index = (start + len - i) % len;
+            if self.slots[index]
+                .compare_exchange(
+                    std::ptr::null_mut(),
+                    ptr.as_ptr(),
+                    Ordering::Release,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                self.hint.store(index, Ordering::Relaxed);
+                return;
+            }
+        }
+
+        // SAFETY: the buffer was allocated by `alloc` with the same size
+        unsafe { free(ptr, self.buffer_size) };
+    }
+}
+
+impl Drop for PoolInner {
+    fn drop(&mut self) {
+        for slot in self.slots.iter_mut() {
+            if let Some(ptr) = NonNull::new(*slot.get_mut()) {
+                // SAFETY: the buffer was allocated by `alloc` with the same size
+                unsafe { free(ptr, self.buffer_size) };
+            }
+        }
+    }
+}
+
+/// Packet buffer which returns to its pool when dropped.
+///
+/// Send side buffers are dropped when the packet is acknowledged,
+/// receive side buffers are dropped after delivery to the application.
+pub struct PacketBuffer {
+    data: NonNull<u8>,
+    /// Number of filled bytes
+    len: usize,
+    pool: Arc<PoolInner>,
+}
+
+// SAFETY: the buffer is uniquely owned
+unsafe impl Send for PacketBuffer {}
+unsafe impl Sync for PacketBuffer {}
+
+impl PacketBuffer {
+    pub fn capacity(&self) -> usize {
+        self.pool.buffer_size
+    }
+
+    /// The whole buffer to serialize a packet into
+    pub fn space_mut(&mut self) -> &mut [u8] {
+        // SAFETY: the buffer is uniquely owned and has `capacity` initialized bytes
+        unsafe { std::slice::from_raw_parts_mut(self.data.as_ptr(), self.capacity()) }
+    }
+
+    /// Sets the number of filled bytes
+    pub fn set_len(&mut self, len: usize) {
+        assert!(len <= self.capacity());
+        self.len = len;
+    }
+
+    /// Fills the buffer using one of the packet `serialize` functions
+    pub fn fill<E, F>(&mut self, f: F) -> Result<&[u8], E>
+    where
+        F: FnOnce(&mut [u8]) -> Result<&[u8], E>,
+    {
+        let len = f(self.space_mut())?.len();
+        self.set_len(len);
+        Ok(self)
+    }
+
+    /// Receives a datagram into the buffer and returns its source address
+    pub fn recv_from<T: Transport>(&mut self, transport: &T) -> io::Result<T::Addr> {
+        let (len, addr) = transport.recv_from(self.space_mut())?;
+        self.set_len(len);
+        Ok(addr)
+    }
+}
+
+impl Deref for PacketBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: the buffer is uniquely owned and has `capacity >= len` initialized bytes
+        unsafe { std::slice::from_raw_parts(self.data.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for PacketBuffer {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: the buffer is uniquely owned and has `capacity >= len` initialized bytes
+        unsafe { std::slice::from_raw_parts_mut(self.data.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for PacketBuffer {
+    fn drop(&mut self) {
+        self.pool.put(self.data);
+    }
+}
+
+impl std::fmt::Debug for PacketBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PacketBuffer")
+            .field("len", &self.len)
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+/// Sent packets waiting for acknowledgement
+#[derive(Debug)]
+pub struct SendBuffer {
+    packets: VecDeque<PacketBuffer>,
+    /// Sequence number of the first packet in the buffer
+    first_seq_no: u32,
+}
+
+impl SendBuffer {
+    pub fn new(isn: u32) -> Self {
+        Self {
+            packets: Default::default(),
+            first_seq_no: isn,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    /// Adds the packet and returns its sequence number
+    pub fn push(&mut self, packet: PacketBuffer) -> u32 {
+        let seq_no = seq_add(self.first_seq_no, self.packets.len() as u32);
+        self.packets.push_back(packet);
+        seq_no
+    }
+
+    /// Finds the packet for retransmission
+    pub fn get(&self, seq_no: u32) -> Option<&PacketBuffer> {
+        let offset = seq_offset(self.first_seq_no, seq_no);
+        if offset < 0 {
+            return None;
+        }
+        self.packets.get(offset as usize)
+    }
+
+    /// Releases all packets before `received_last_ack` (excluding)
+    pub fn acknowledge(&mut self, received_last_ack: u32) {
+        while !self.packets.is_empty() && seq_cmp(self.first_seq_no, received_last_ack) < 0 {
+            self.packets.pop_front();
+            self.first_seq_no = seq_inc(self.first_seq_no);
+        }
+    }
+}
+
+/// Received packets waiting for delivery to the application.
+///
+/// Packets may arrive out of order, they are delivered in sequence order and their
+/// buffers return to the pool when the application drops them.
+#[derive(Debug)]
+pub struct ReceiveBuffer {
+    packets: VecDeque<Option<PacketBuffer>>,
+    /// Sequence number of the next packet to deliver
+    first_seq_no: u32,
+    /// Maximum number of packets ahead of `first_seq_no`
+    capacity: usize,
+}
+
+impl ReceiveBuffer {
+    pub fn new(isn: u32, capacity: usize) -> Self {
+        Self {
+            packets: VecDeque::with_capacity(capacity),
+            first_seq_no: isn,
+            capacity,
+        }
+    }
+
+    /// Stores the received packet.
+    ///
+    /// Returns `false` for duplicates and packets outside of the buffer, which are
+    /// dropped (and so returned to the pool) right away
+    pub fn insert(&mut self, seq_no: u32, packet: PacketBuffer) -> bool {
+        let offset = seq_offset(self.first_seq_no, seq_no);
+        if offset < 0 || offset as usize >= self.capacity {
+            return false;
+        }
+
+        let offset = offset as usize;
+        if offset >= self.packets.len() {
+            self.packets.resize_with(offset + 1, || None);
+        }
+        let slot = &mut self.packets[offset];
+        if slot.is_some() {
+            return false;
+        }
+        *slot = Some(packet);
+        true
+    }
+
+    /// Number of packets which can still be stored ahead of the next packet to deliver
+    pub fn available(&self) -> usize {
+        self.capacity - self.packets.len()
+    }
+
+    /// Whether the next packet in sequence order has arrived
+    pub fn has_next(&self) -> bool {
+        matches!(self.packets.front(), Some(Some(_)))
+    }
+
+    /// Takes the next packet in sequence order if it has arrived
+    pub fn pop(&mut self) -> Option<PacketBuffer> {
+        let packet = self.packets.front_mut()?.take()?;
+        self.packets.pop_front();
+        self.first_seq_no = seq_inc(self.first_seq_no);
+        Some(packet)
+    }
+}
+
+fn alloc(size: usize) -> NonNull<u8> {
+    let data = vec![0u8; size].into_boxed_slice();
+    // SAFETY: `Box::into_raw` never returns null
+    unsafe { NonNull::new_unchecked(Box::into_raw(data) as *mut u8) }
+}
+
+/// # Safety
+/// `ptr` must be allocated by [`alloc`] with the same `size`
+unsafe fn free(ptr: NonNull<u8>, size: usize) {
+    drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+        ptr.as_ptr(),
+        size,
+    )));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::PacketHeader;
+    use crate::seq::MAX_SEQ_NO;
+    use crate::transport::MemoryTransport;
+
+    #[test]
+    fn buffers_are_reused() {
+        let pool = BufferPool::new(1500, 2);
+        assert_eq!(pool.available(), 2);
+
+        let mut buffer = pool.acquire();
+        let header = PacketHeader {
+            seq_no: 1,
+            msg_no: 2,
+            timestamp: 3,
+            id: 4,
+        };
+        assert_eq!(
+            buffer
+                .fill(|buffer| header.serialize(buffer))
+                .unwrap()
+                .len(),
+            16
+        );
+        assert_eq!(buffer.len(), 16);
+        assert_eq!(pool.available(), 1);
+
+        let ptr = buffer.as_ptr();
+        drop(buffer);
+        assert_eq!(pool.available(), 2);
+
+        let buffers = [pool.acquire(), pool.acquire(), pool.acquire()];
+        assert!(buffers.iter().any(|buffer| buffer.as_ptr() == ptr));
+        assert_eq!(pool.available(), 0);
+
+        drop(buffers);
+        assert_eq!(pool.available(), 2);
+    }
+
+    #[test]
+    fn concurrent_acquire_release() {
+        let pool = BufferPool::new(64, 16);
+
+        let threads = (0..4)
+            .map(|_| {
+                let pool = pool.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        let mut buffers = [pool.acquire(), pool.acquire(), pool.acquire()];
+                        for buffer in &mut buffers {
+                            buffer.space_mut().fill(1);
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(pool.available(), 16);
+    }
+
+    #[test]
+    fn acknowledged_packets_are_released() {
+        let pool = BufferPool::new(64, 8);
+
+        let mut send_buffer = SendBuffer::new(MAX_SEQ_NO - 1);
+        for _ in 0..4 {
+            send_buffer.push(pool.acquire());
+        }
+        assert_eq!(pool.available(), 4);
+        assert!(send_buffer.get(0).is_some());
+        assert!(send_buffer.get(2).is_none());
+
+        send_buffer.acknowledge(1);
+        assert_eq!(send_buffer.len(), 1);
+        assert_eq!(pool.available(), 7);
+    }
+
+    #[test]
+    fn received_packets_are_released_after_delivery() {
+        let pool = BufferPool::new(64, 8);
+        let (sender, receiver) = MemoryTransport::pair();
+
+        let mut receive_buffer = ReceiveBuffer::new(MAX_SEQ_NO, 4);
+        for seq_no in [0, MAX_SEQ_NO, 0, 4] {
+            sender
+                .send_to(&[seq_no as u8; 10], &sender.peer_addr())
+                .unwrap();
+
+            let mut buffer = pool.acquire();
+            buffer.recv_from(&receiver).unwrap();
+            receive_buffer.insert(seq_no, buffer);
+        }
+        // The duplicate and the packet outside of the buffer are released
+        assert_eq!(pool.available(), 6);
+
+        let first = receive_buffer.pop().unwrap();
+        assert_eq!(*first, [MAX_SEQ_NO as u8; 10]);
+        let second = receive_buffer.pop().unwrap();
+        assert_eq!(*second, [0; 10]);
+        assert!(receive_buffer.pop().is_none());
+        assert_eq!(pool.available(), 6);
+
+        drop((first, second));
+        assert_eq!(pool.available(), 8);
+    }
+}
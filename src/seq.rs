@@ -0,0 +1,81 @@
+/// Maximum packet sequence number (31 bits)
+pub const MAX_SEQ_NO: u32 = 0x7fffffff;
+/// Sequence number comparison threshold
+const SEQ_NO_TH: u32 = 0x3fffffff;
+
+/// Maximum message number (29 bits)
+pub const MAX_MSG_NO: u32 = 0x1fffffff;
+
+/// Compares two sequence numbers taking wraparound into account
+pub fn seq_cmp(a: u32, b: u32) -> i32 {
+    if a.abs_diff(b) < SEQ_NO_TH {
+        a as i32 - b as i32
+    } else {
+        b as i32 - a as i32
+    }
+}
+
+/// Signed distance from `first` to `second` taking wraparound into account
+pub fn seq_offset(first: u32, second: u32) -> i32 {
+    let (first, second) = (first as i64, second as i64);
+    let offset = if (first - second).abs() < SEQ_NO_TH as i64 {
+        second - first
+    } else if first < second {
+        second - first - MAX_SEQ_NO as i64 - 1
+    } else {
+        second - first + MAX_SEQ_NO as i64 + 1
+    };
+    offset as i32
+}
+
+/// Number of sequence numbers in the inclusive range `first..=last`
+pub fn seq_len(first: u32, last: u32) -> u32 {
+    if first <= last {
+        last - first + 1
+    } else {
+        last + MAX_SEQ_NO - first + 2
+    }
+}
+
+/// Next sequence number
+pub fn seq_inc(seq_no: u32) -> u32 {
+    if seq_no == MAX_SEQ_NO {
+        0
+    } else {
+        seq_no + 1
+    }
+}
+
+/// Sequence number advanced by `count`
+pub fn seq_add(seq_no: u32, count: u32) -> u32 {
+    // Advancing by the whole sequence space is a no-op
+    let count = count % (MAX_SEQ_NO + 1);
+    if MAX_SEQ_NO - seq_no >= count {
+        seq_no + count
+    } else {
+        seq_no - (MAX_SEQ_NO - count) - 1
+    }
+}
+
+/// Next message number
+pub fn msg_inc(msg_no: u32) -> u32 {
+    if msg_no == MAX_MSG_NO {
+        1
+    } else {
+        msg_no + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seq_add_wraps_around() {
+        assert_eq!(seq_add(5, 10), 15);
+        assert_eq!(seq_add(MAX_SEQ_NO - 1, 3), 1);
+        assert_eq!(seq_add(MAX_SEQ_NO, MAX_SEQ_NO), MAX_SEQ_NO - 1);
+        assert_eq!(seq_add(10, MAX_SEQ_NO + 1), 10);
+        assert_eq!(seq_add(10, u32::MAX), 9);
+    }
+}
@@ -0,0 +1,533 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::transport::Transport;
+
+/// Impairments of a one-way link
+#[derive(Debug, Copy, Clone)]
+pub struct LinkConfig {
+    /// One-way propagation delay
+    pub latency: Duration,
+    /// Maximum random delay added to each packet
+    pub jitter: Duration,
+    /// Probability of a packet loss
+    pub loss_rate: f64,
+    /// Optional bursty loss model
+    pub burst_loss: Option<BurstLoss>,
+    /// Probability of a packet being delayed to arrive out of order
+    pub reorder_rate: f64,
+    /// Extra delay of a reordered packet
+    pub reorder_delay: Duration,
+    /// Probability of a packet being duplicated
+    pub duplicate_rate: f64,
+    /// Link capacity (in bytes per second), `None` or 0 means unlimited
+    pub bandwidth: Option<u64>,
+    /// Bottleneck queue size (in bytes), packets are tail-dropped when it is full
+    pub queue_limit: Option<usize>,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        Self {
+            latency: Duration::from_millis(10),
+            jitter: Duration::ZERO,
+            loss_rate: 0.0,
+            burst_loss: None,
+            reorder_rate: 0.0,
+            reorder_delay: Duration::from_millis(10),
+            duplicate_rate: 0.0,
+            bandwidth: None,
+            queue_limit: None,
+        }
+    }
+}
+
+/// Gilbert-Elliott loss model
+#[derive(Debug, Copy, Clone)]
+pub struct BurstLoss {
+    /// Probability to switch from the good state to the bad state
+    pub enter_probability: f64,
+    /// Probability to switch from the bad state to the good state
+    pub exit_probability: f64,
+    /// Probability of a packet loss in the bad state
+    pub loss_rate: f64,
+}
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct LinkStats {
+    /// Packets passed to the link
+    pub sent: u64,
+    /// Packets received by the destination
+    pub delivered: u64,
+    /// Packets lost randomly
+    pub lost: u64,
+    /// Packets dropped by the full bottleneck queue
+    pub queue_dropped: u64,
+    /// Extra copies of duplicated packets
+    pub duplicated: u64,
+}
+
+/// In-process simulated network driven by a virtual clock.
+///
+/// Time only advances with [`SimNetwork::advance`], so the same seed
+/// always produces the same packet trace.
+#[derive(Clone)]
+pub struct SimNetwork {
+    inner: Arc<Mutex<NetworkInner>>,
+}
+
+impl SimNetwork {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(NetworkInner {
+                started_at: Instant::now(),
+                now: Duration::ZERO,
+                rng: Rng::new(seed),
+                default_link: Default::default(),
+                links: Default::default(),
+                inboxes: Default::default(),
+                next_packet_id: 0,
+            })),
+        }
+    }
+
+    /// Creates a socket bound to the specified address
+    pub fn bind(&self, addr: SocketAddr) -> io::Result<SimSocket> {
+        let mut inner = self.lock();
+        if inner.inboxes.contains_key(&addr) {
+            return Err(io::ErrorKind::AddrInUse.into());
+        }
+        inner.inboxes.insert(addr, Default::default());
+
+        Ok(SimSocket {
+            network: self.clone(),
+            addr,
+        })
+    }
+
+    /// Link config used for all address pairs without an explicit config
+    pub fn set_default_link(&self, config: LinkConfig) {
+        self.lock().default_link = config;
+    }
+
+    /// Sets the config of the one-way link from `from` to `to`
+    pub fn set_link(&self, from: SocketAddr, to: SocketAddr, config: LinkConfig) {
+        let mut inner = self.lock();
+        inner.links.entry((from, to)).or_default().config = Some(config);
+    }
+
+    pub fn link_stats(&self, from: SocketAddr, to: SocketAddr) -> LinkStats {
+        let inner = self.lock();
+        inner
+            .links
+            .get(&(from, to))
+            .map(|link| link.stats)
+            .unwrap_or_default()
+    }
+
+    /// Virtual time since the network was created
+    pub fn now(&self) -> Duration {
+        self.lock().now
+    }
+
+    /// Virtual time as an [`Instant`] for the time-based protocol components
+    pub fn instant(&self) -> Instant {
+        let inner = self.lock();
+        inner.started_at + inner.now
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.lock().now += duration;
+    }
+
+    /// Arrival time of the next packet in flight
+    pub fn next_arrival(&self) -> Option<Duration> {
+        let inner = self.lock();
+        inner
+            .inboxes
+            .values()
+            .filter_map(|inbox| inbox.peek().map(|Reverse(packet)| packet.arrival))
+            .min()
+    }
+
+    /// Advances the clock to the next packet arrival
+    pub fn advance_to_next_arrival(&self) -> Option<Duration> {
+        let arrival = self.next_arrival()?;
+        let mut inner = self.lock();
+        inner.now = inner.now.max(arrival);
+        Some(inner.now)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, NetworkInner> {
+        self.inner.lock().unwrap()
+    }
+}
+
+/// Socket attached to the simulated network.
+///
+/// Receiving never blocks: [`io::ErrorKind::WouldBlock`] is returned
+/// when no packet has arrived at the current virtual time.
+pub struct SimSocket {
+    network: SimNetwork,
+    addr: SocketAddr,
+}
+
+impl Transport for SimSocket {
+    type Addr = SocketAddr;
+
+    fn send_to(&self, buffer: &[u8], addr: &SocketAddr) -> io::Result<usize> {
+        self.network.lock().send(self.addr, *addr, buffer);
+        Ok(buffer.len())
+    }
+
+    fn recv_from(&self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut inner = self.network.lock();
+        let now = inner.now;
+
+        let inbox = inner.inboxes.get_mut(&self.addr).expect("socket inbox");
+        match inbox.peek() {
+            Some(Reverse(packet)) if packet.arrival <= now => {}
+            _ => return Err(io::ErrorKind::WouldBlock.into()),
+        }
+        let Reverse(packet) = inbox.pop().expect("peeked packet");
+
+        if let Some(link) = inner.links.get_mut(&(packet.from, self.addr)) {
+            link.stats.delivered += 1;
+        }
+
+        let len = packet.data.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&packet.data[..len]);
+        Ok((len, packet.from))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    /// Receiving never blocks, so only non-blocking mode is supported
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        if nonblocking {
+            Ok(())
+        } else {
+            Err(io::ErrorKind::Unsupported.into())
+        }
+    }
+}
+
+impl Drop for SimSocket {
+    fn drop(&mut self) {
+        self.network.lock().inboxes.remove(&self.addr);
+    }
+}
+
+struct NetworkInner {
+    started_at: Instant,
+    now: Duration,
+    rng: Rng,
+    default_link: LinkConfig,
+    links: HashMap<(SocketAddr, SocketAddr), LinkState>,
+    inboxes: HashMap<SocketAddr, BinaryHeap<Reverse<InFlightPacket>>>,
+    next_packet_id: u64,
+}
+
+impl NetworkInner {
+    fn send(&mut self, from: SocketAddr, to: SocketAddr, data: &[u8]) {
+        let now = self.now;
+        let default_link = self.default_link;
+        let link = self.links.entry((from, to)).or_default();
+        let config = link.config.unwrap_or(default_link);
+        link.stats.sent += 1;
+
+        // Bottleneck queue and serialization delay
+        let mut departure = now;
+        if let Some(bandwidth) = config.bandwidth.filter(|&bandwidth| bandwidth > 0) {
+            let busy_until = link.busy_until.max(now);
+            let queued = (busy_until - now).as_secs_f64() * bandwidth as f64;
+            if matches!(config.queue_limit, Some(limit) if queued + data.len() as f64 > limit as f64)
+            {
+                link.stats.queue_dropped += 1;
+                return;
+            }
+
+            departure = busy_until + Duration::from_secs_f64(data.len() as f64 / bandwidth as f64);
+            link.busy_until = departure;
+        }
+
+        // Random and bursty loss
+        let mut loss_rate = config.loss_rate;
+        if let Some(burst) = config.burst_loss {
+            let switch = if link.bad_state {
+                burst.exit_probability
+            } else {
+                burst.enter_probability
+            };
+            if self.rng.chance(switch) {
+                link.bad_state = !link.bad_state;
+            }
+            if link.bad_state {
+                loss_rate = burst.loss_rate;
+            }
+        }
+        if self.rng.chance(loss_rate) {
+            link.stats.lost += 1;
+            return;
+        }
+
+        let copies = if self.rng.chance(config.duplicate_rate) {
+            link.stats.duplicated += 1;
+            2
+        } else {
+            1
+        };
+
+        for _ in 0..copies {
+            let mut arrival = departure + config.latency + self.rng.duration(config.jitter);
+            if self.rng.chance(config.reorder_rate) {
+                arrival += config.reorder_delay;
+            } else {
+                // Keep the link FIFO unless the packet is reordered on purpose
+                arrival = arrival.max(link.last_arrival);
+                link.last_arrival = arrival;
+            }
+
+            let Some(inbox) = self.inboxes.get_mut(&to) else {
+                return;
+            };
+
+            inbox.push(Reverse(InFlightPacket {
+                arrival,
+                id: self.next_packet_id,
+                from,
+                data: data.to_vec(),
+            }));
+            self.next_packet_id += 1;
+        }
+    }
+}
+
+#[derive(Default)]
+struct LinkState {
+    config: Option<LinkConfig>,
+    stats: LinkStats,
+    /// Time when the bottleneck finishes sending the last queued packet
+    busy_until: Duration,
+    /// Arrival time of the last in-order packet
+    last_arrival: Duration,
+    /// Gilbert-Elliott state
+    bad_state: bool,
+}
+
+#[derive(Eq, PartialEq)]
+struct InFlightPacket {
+    arrival: Duration,
+    /// Tie breaker for packets with the same arrival time
+    id: u64,
+    from: SocketAddr,
+    data: Vec<u8>,
+}
+
+impl Ord for InFlightPacket {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.arrival, self.id).cmp(&(other.arrival, other.id))
+    }
+}
+
+impl PartialOrd for InFlightPacket {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// SplitMix64 generator, good enough for reproducible impairments
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform value in `[0, 1)`
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.next_f64() < probability
+    }
+
+    fn duration(&mut self, max: Duration) -> Duration {
+        if max.is_zero() {
+            return max;
+        }
+        max.mul_f64(self.next_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 1], port))
+    }
+
+    /// Sends `count` numbered packets one per `interval` and returns arrival order and times
+    fn run(network: &SimNetwork, count: u32, interval: Duration) -> Vec<(u32, Duration)> {
+        let sender = network.bind(addr(1)).unwrap();
+        let receiver = network.bind(addr(2)).unwrap();
+
+        let mut received = Vec::new();
+        let mut buffer = [0; 1500];
+        let mut receive = |received: &mut Vec<_>| {
+            while let Ok((len, from)) = receiver.recv_from(&mut buffer) {
+                assert_eq!(from, addr(1));
+                let seq = u32::from_le_bytes(buffer[..4].try_into().unwrap());
+                assert_eq!(len, 1000);
+                received.push((seq, network.now()));
+            }
+        };
+
+        for i in 0..count {
+            let mut packet = [0; 1000];
+            packet[..4].copy_from_slice(&i.to_le_bytes());
+            sender.send_to(&packet, &addr(2)).unwrap();
+            network.advance(interval);
+            receive(&mut received);
+        }
+        while network.advance_to_next_arrival().is_some() {
+            receive(&mut received);
+        }
+
+        received
+    }
+
+    #[test]
+    fn same_seed_same_trace() {
+        let config = LinkConfig {
+            jitter: Duration::from_millis(5),
+            loss_rate: 0.1,
+            reorder_rate: 0.05,
+            duplicate_rate: 0.05,
+            ..Default::default()
+        };
+
+        let traces = [1, 1, 2].map(|seed| {
+            let network = SimNetwork::new(seed);
+            network.set_default_link(config);
+            run(&network, 500, Duration::from_millis(1))
+        });
+
+        assert_eq!(traces[0], traces[1]);
+        assert_ne!(traces[0], traces[2]);
+    }
+
+    #[test]
+    fn loss_and_duplication_rates() {
+        let network = SimNetwork::new(42);
+        network.set_default_link(LinkConfig {
+            loss_rate: 0.2,
+            duplicate_rate: 0.1,
+            ..Default::default()
+        });
+
+        let received = run(&network, 5000, Duration::from_micros(100));
+        let stats = network.link_stats(addr(1), addr(2));
+        assert_eq!(stats.sent, 5000);
+        assert_eq!(stats.delivered as usize, received.len());
+        assert_eq!(stats.delivered, stats.sent - stats.lost + stats.duplicated);
+        assert!((900..1100).contains(&stats.lost), "{stats:?}");
+        assert!((300..500).contains(&stats.duplicated), "{stats:?}");
+    }
+
+    #[test]
+    fn bursty_loss_is_clustered() {
+        let network = SimNetwork::new(7);
+        network.set_default_link(LinkConfig {
+            burst_loss: Some(BurstLoss {
+                enter_probability: 0.01,
+                exit_probability: 0.1,
+                loss_rate: 1.0,
+            }),
+            ..Default::default()
+        });
+
+        let received = run(&network, 5000, Duration::from_micros(100));
+        let gaps = received
+            .windows(2)
+            .map(|pair| pair[1].0 - pair[0].0 - 1)
+            .filter(|gap| *gap > 0)
+            .collect::<Vec<_>>();
+
+        let lost = gaps.iter().sum::<u32>();
+        assert!(lost > 0);
+        // Average burst length is 1 / exit_probability
+        assert!(lost as usize / gaps.len() >= 5, "{gaps:?}");
+    }
+
+    #[test]
+    fn in_order_without_reordering() {
+        let network = SimNetwork::new(3);
+        network.set_default_link(LinkConfig {
+            jitter: Duration::from_millis(20),
+            ..Default::default()
+        });
+
+        let received = run(&network, 1000, Duration::from_micros(100));
+        assert_eq!(received.len(), 1000);
+        assert!(received.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn bandwidth_limit_and_queue() {
+        let network = SimNetwork::new(0);
+        network.set_default_link(LinkConfig {
+            latency: Duration::from_millis(50),
+            bandwidth: Some(1_000_000),
+            queue_limit: Some(50_000),
+            ..Default::default()
+        });
+
+        // Offered load is 10 MB/s over a 1 MB/s link
+        let received = run(&network, 1000, Duration::from_micros(100));
+        let stats = network.link_stats(addr(1), addr(2));
+        assert!(stats.queue_dropped > 0);
+
+        // Packets leave the bottleneck exactly every 1 ms
+        for pair in received.windows(2) {
+            let interval = pair[1].1 - pair[0].1;
+            assert!(interval <= Duration::from_micros(1100), "{interval:?}");
+        }
+
+        // Queueing delay is bounded by the queue size
+        let last = received.last().unwrap().1;
+        assert!(last <= Duration::from_millis(100 + 50 + 51));
+    }
+
+    #[test]
+    fn zero_bandwidth_is_unlimited() {
+        let network = SimNetwork::new(0);
+        network.set_default_link(LinkConfig {
+            bandwidth: Some(0),
+            queue_limit: Some(1000),
+            ..Default::default()
+        });
+
+        let received = run(&network, 100, Duration::ZERO);
+        assert_eq!(received.len(), 100);
+        assert!(received
+            .iter()
+            .all(|(_, time)| *time == Duration::from_millis(10)));
+    }
+}
@@ -0,0 +1,170 @@
+use std::time::{Duration, Instant};
+
+use crate::error::ConnectionError;
+
+/// Default linger timeout for the graceful close
+pub const DEFAULT_LINGER: Duration = Duration::from_secs(180);
+
+/// Connection lifecycle state with graceful close semantics
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ConnectionState {
+    /// Connection is established
+    Connected,
+    /// Connection was closed locally, waiting for the send buffer to drain
+    Lingering {
+        /// Time after which the remaining data is discarded
+        deadline: Instant,
+    },
+    /// Shutdown packet was received, remaining data can still be read
+    PeerClosed,
+    /// Connection no longer exists
+    Closed,
+}
+
+impl ConnectionState {
+    /// Starts the graceful close.
+    ///
+    /// The Shutdown packet is sent by [`ConnectionState::poll_linger`]
+    /// once the send buffer is drained or the linger timeout expires.
+    /// If the peer has already closed the connection, there is nobody to
+    /// deliver the data to and the connection is closed right away.
+    pub fn close(&mut self, now: Instant, linger: Duration) -> Result<(), ConnectionError> {
+        match self {
+            Self::Connected => {
+                let deadline = now
+                    .checked_add(linger)
+                    .ok_or(ConnectionError::InvalidLinger)?;
+                *self = Self::Lingering { deadline };
+                Ok(())
+            }
+            Self::PeerClosed => {
+                *self = Self::Closed;
+                Ok(())
+            }
+            Self::Lingering { .. } | Self::Closed => Err(ConnectionError::NotExist),
+        }
+    }
+
+    /// Checks the lingering connection.
+    ///
+    /// Returns `true` when the Shutdown packet must be sent
+    pub fn poll_linger(&mut self, now: Instant, send_buffer_size: usize) -> bool {
+        match self {
+            Self::Lingering { deadline } if send_buffer_size == 0 || now >= *deadline => {
+                *self = Self::Closed;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Handles the Shutdown packet from the peer
+    pub fn on_shutdown(&mut self) {
+        *self = match self {
+            Self::Connected => Self::PeerClosed,
+            _ => Self::Closed,
+        };
+    }
+
+    /// Checks whether data can be read with `available` bytes in the receive buffer.
+    ///
+    /// EOF is reported once after all remaining data is consumed,
+    /// subsequent calls return [`ConnectionError::NotExist`]
+    pub fn check_read(&mut self, available: usize) -> Result<ReadStatus, ConnectionError> {
+        match self {
+            Self::Connected if available > 0 => Ok(ReadStatus::Ready),
+            Self::Connected => Ok(ReadStatus::Pending),
+            Self::PeerClosed if available > 0 => Ok(ReadStatus::Ready),
+            Self::PeerClosed => {
+                *self = Self::Closed;
+                Ok(ReadStatus::Eof)
+            }
+            Self::Lingering { .. } | Self::Closed => Err(ConnectionError::NotExist),
+        }
+    }
+
+    /// Checks whether data can be written
+    pub fn check_write(&self) -> Result<(), ConnectionError> {
+        match self {
+            Self::Connected => Ok(()),
+            Self::PeerClosed => Err(ConnectionError::Broken),
+            Self::Lingering { .. } | Self::Closed => Err(ConnectionError::NotExist),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ReadStatus {
+    /// Data is available
+    Ready,
+    /// No data yet
+    Pending,
+    /// Peer closed the connection and all data was consumed
+    Eof,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linger_until_drained_or_timeout() {
+        let now = Instant::now();
+        let linger = Duration::from_secs(1);
+
+        let mut state = ConnectionState::Connected;
+        state.close(now, linger).unwrap();
+        assert!(!state.poll_linger(now, 100));
+        assert!(state.poll_linger(now, 0));
+        assert_eq!(state, ConnectionState::Closed);
+        assert!(matches!(
+            state.close(now, linger),
+            Err(ConnectionError::NotExist)
+        ));
+
+        let mut state = ConnectionState::Connected;
+        state.close(now, linger).unwrap();
+        assert!(!state.poll_linger(now + linger / 2, 100));
+        assert!(state.poll_linger(now + linger, 100));
+        assert!(matches!(
+            state.check_write(),
+            Err(ConnectionError::NotExist)
+        ));
+    }
+
+    #[test]
+    fn read_remaining_data_after_shutdown() {
+        let mut state = ConnectionState::Connected;
+        assert_eq!(state.check_read(0).unwrap(), ReadStatus::Pending);
+
+        state.on_shutdown();
+        assert_eq!(state.check_read(10).unwrap(), ReadStatus::Ready);
+        assert_eq!(state.check_read(0).unwrap(), ReadStatus::Eof);
+        assert!(matches!(
+            state.check_read(0),
+            Err(ConnectionError::NotExist)
+        ));
+    }
+
+    #[test]
+    fn close_after_shutdown_does_not_linger() {
+        let now = Instant::now();
+
+        let mut state = ConnectionState::Connected;
+        state.on_shutdown();
+        state.close(now, DEFAULT_LINGER).unwrap();
+        assert_eq!(state, ConnectionState::Closed);
+        // Nothing to send, the peer is gone
+        assert!(!state.poll_linger(now, 100));
+    }
+
+    #[test]
+    fn huge_linger_is_rejected() {
+        let mut state = ConnectionState::Connected;
+        assert!(matches!(
+            state.close(Instant::now(), Duration::MAX),
+            Err(ConnectionError::InvalidLinger)
+        ));
+        assert_eq!(state, ConnectionState::Connected);
+    }
+}
@@ -0,0 +1,54 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+pub fn write_sockaddr(addr: &SocketAddr, storage: &mut libc::sockaddr_storage) {
+    match addr {
+        SocketAddr::V4(addr) => {
+            // SAFETY: `sockaddr_storage` is large enough and suitably aligned for `sockaddr_in`
+            let sin = unsafe { &mut *(storage as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = addr.port().to_be();
+            sin.sin_addr.s_addr = u32::from_ne_bytes(addr.ip().octets());
+        }
+        SocketAddr::V6(addr) => {
+            // SAFETY: `sockaddr_storage` is large enough and suitably aligned for `sockaddr_in6`
+            let sin6 = unsafe { &mut *(storage as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = addr.port().to_be();
+            sin6.sin6_flowinfo = addr.flowinfo();
+            sin6.sin6_addr.s6_addr = addr.ip().octets();
+            sin6.sin6_scope_id = addr.scope_id();
+        }
+    }
+}
+
+pub fn read_sockaddr(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+    match storage.ss_family as libc::c_int {
+        libc::AF_INET => {
+            // SAFETY: address family is checked
+            let sin = unsafe { &*(storage as *const _ as *const libc::sockaddr_in) };
+            Some(SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::from(sin.sin_addr.s_addr.to_ne_bytes()),
+                u16::from_be(sin.sin_port),
+            )))
+        }
+        libc::AF_INET6 => {
+            // SAFETY: address family is checked
+            let sin6 = unsafe { &*(storage as *const _ as *const libc::sockaddr_in6) };
+            Some(SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::from(sin6.sin6_addr.s6_addr),
+                u16::from_be(sin6.sin6_port),
+                sin6.sin6_flowinfo,
+                sin6.sin6_scope_id,
+            )))
+        }
+        _ => None,
+    }
+}
+
+pub fn sockaddr_len(storage: &libc::sockaddr_storage) -> libc::socklen_t {
+    let len = match storage.ss_family as libc::c_int {
+        libc::AF_INET => std::mem::size_of::<libc::sockaddr_in>(),
+        _ => std::mem::size_of::<libc::sockaddr_in6>(),
+    };
+    len as libc::socklen_t
+}
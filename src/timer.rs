@@ -0,0 +1,187 @@
+use std::time::{Duration, Instant};
+
+use crate::error::ConnectionError;
+
+/// Periodic timer interval (SYN)
+pub const SYN_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Copy, Clone)]
+pub struct ExpTimerConfig {
+    /// Number of expirations after which the peer may be declared dead
+    pub max_exp_count: u32,
+    /// Minimum time without any response before the peer may be declared dead
+    pub min_broken_time: Duration,
+    /// Lower bound of the expiration interval
+    pub min_exp_interval: Duration,
+    /// Idle period after which a keep-alive packet is sent
+    pub keep_alive_interval: Duration,
+}
+
+impl Default for ExpTimerConfig {
+    fn default() -> Self {
+        Self {
+            max_exp_count: 16,
+            min_broken_time: Duration::from_secs(10),
+            min_exp_interval: Duration::from_millis(300),
+            keep_alive_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Expiration (EXP) timer which detects peer death and schedules keep-alive packets
+#[derive(Debug)]
+pub struct ExpTimer {
+    config: ExpTimerConfig,
+    /// Number of continuous expirations
+    exp_count: u32,
+    /// Last time when any packet was received from the peer
+    last_response_time: Instant,
+    /// Last time when the timer expired or was reset by the peer response
+    last_exp_time: Instant,
+    /// Last time when any packet was sent to the peer
+    last_sent_time: Instant,
+}
+
+impl ExpTimer {
+    pub fn new(config: ExpTimerConfig, now: Instant) -> Self {
+        Self {
+            config,
+            exp_count: 1,
+            last_response_time: now,
+            last_exp_time: now,
+            last_sent_time: now,
+        }
+    }
+
+    pub fn exp_count(&self) -> u32 {
+        self.exp_count
+    }
+
+    pub fn last_response_time(&self) -> Instant {
+        self.last_response_time
+    }
+
+    /// Must be called on every packet received from the peer (including keep-alive)
+    pub fn on_response(&mut self, now: Instant) {
+        self.exp_count = 1;
+        self.last_response_time = now;
+        self.last_exp_time = now;
+    }
+
+    /// Must be called on every packet sent to the peer
+    pub fn on_packet_sent(&mut self, now: Instant) {
+        self.last_sent_time = now;
+    }
+
+    /// Checks the timer and returns an action which must be performed.
+    ///
+    /// Returns [`ConnectionError::Broken`] when the peer has not responded for at least
+    /// `max_exp_count` expirations and `min_broken_time`.
+    pub fn check(
+        &mut self,
+        now: Instant,
+        rtt: Duration,
+        rtt_var: Duration,
+        has_unacked_data: bool,
+    ) -> Result<Option<ExpAction>, ConnectionError> {
+        let exp_interval = std::cmp::max(
+            (rtt + rtt_var * 4) * self.exp_count + SYN_INTERVAL,
+            self.config.min_exp_interval * self.exp_count,
+        );
+
+        if now.saturating_duration_since(self.last_exp_time) > exp_interval {
+            // Haven't received any information from the peer, is it dead?
+            if self.exp_count > self.config.max_exp_count
+                && now.saturating_duration_since(self.last_response_time)
+                    >= self.config.min_broken_time
+            {
+                return Err(ConnectionError::Broken);
+            }
+
+            self.exp_count += 1;
+            self.last_exp_time = now;
+
+            // Sender: all packets sent after the last ACK must be retransmitted.
+            // Receiver: send a keep-alive packet.
+            return Ok(Some(if has_unacked_data {
+                ExpAction::Retransmit
+            } else {
+                ExpAction::KeepAlive
+            }));
+        }
+
+        // Send a keep-alive packet if nothing has been sent for a while
+        if now.saturating_duration_since(self.last_sent_time) >= self.config.keep_alive_interval {
+            return Ok(Some(ExpAction::KeepAlive));
+        }
+
+        Ok(None)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ExpAction {
+    /// Packets sent after the last received ACK must be moved into the sender loss list
+    Retransmit,
+    /// Keep-alive packet must be sent
+    KeepAlive,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_connection_sends_keep_alive() {
+        let start = Instant::now();
+        let mut timer = ExpTimer::new(Default::default(), start);
+
+        let rtt = Duration::from_millis(1);
+        let mut now = start;
+        for _ in 0..9 {
+            now += Duration::from_millis(100);
+            timer.on_response(now);
+            assert_eq!(timer.check(now, rtt, rtt, false).unwrap(), None);
+        }
+
+        now += Duration::from_millis(100);
+        timer.on_response(now);
+        assert_eq!(
+            timer
+                .check(now + Duration::from_millis(1), rtt, rtt, false)
+                .unwrap(),
+            Some(ExpAction::KeepAlive)
+        );
+
+        timer.on_packet_sent(now);
+        assert_eq!(timer.check(now, rtt, rtt, false).unwrap(), None);
+    }
+
+    #[test]
+    fn silent_peer_is_broken() {
+        let config = ExpTimerConfig {
+            max_exp_count: 4,
+            min_broken_time: Duration::from_secs(5),
+            ..Default::default()
+        };
+
+        let start = Instant::now();
+        let mut timer = ExpTimer::new(config, start);
+
+        let rtt = Duration::from_millis(1);
+        let mut now = start;
+        let mut result = Ok(None);
+        for _ in 0..100 {
+            now += Duration::from_millis(100);
+            timer.on_packet_sent(now);
+            result = timer.check(now, rtt, rtt, true);
+            if result.is_err() {
+                break;
+            }
+        }
+
+        assert!(matches!(result, Err(ConnectionError::Broken)));
+        assert!(timer.exp_count() > config.max_exp_count);
+        assert!(now - start >= config.min_broken_time);
+    }
+}
@@ -0,0 +1,355 @@
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Datagram transport which carries UDT packets
+pub trait Transport {
+    /// Transport specific peer address
+    type Addr: Clone + Eq + Hash + Debug;
+
+    /// Sends a datagram to the specified address
+    fn send_to(&self, buffer: &[u8], addr: &Self::Addr) -> io::Result<usize>;
+
+    /// Receives a datagram and returns its size and source address
+    fn recv_from(&self, buffer: &mut [u8]) -> io::Result<(usize, Self::Addr)>;
+
+    /// Address this transport is bound to
+    fn local_addr(&self) -> io::Result<Self::Addr>;
+
+    /// Switches receiving between blocking and non-blocking mode
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+
+    /// Blocks until a datagram can be received or `timeout` expires, returns `false` on
+    /// timeout. The transport is left in non-blocking mode.
+    ///
+    /// Transports which can't wait report [`io::ErrorKind::Unsupported`]
+    fn wait_readable(&self, _timeout: Option<Duration>) -> io::Result<bool> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+impl Transport for UdpSocket {
+    type Addr = SocketAddr;
+
+    fn send_to(&self, buffer: &[u8], addr: &Self::Addr) -> io::Result<usize> {
+        UdpSocket::send_to(self, buffer, addr)
+    }
+
+    fn recv_from(&self, buffer: &mut [u8]) -> io::Result<(usize, Self::Addr)> {
+        UdpSocket::recv_from(self, buffer)
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        UdpSocket::local_addr(self)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UdpSocket::set_nonblocking(self, nonblocking)
+    }
+
+    fn wait_readable(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        // Zero read timeout is rejected
+        self.set_read_timeout(timeout.map(|timeout| timeout.max(Duration::from_micros(1))))?;
+        UdpSocket::set_nonblocking(self, false)?;
+        let result = self.peek_from(&mut [0; 1]);
+        UdpSocket::set_nonblocking(self, true)?;
+
+        match result {
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Ok(false)
+            }
+            // Errors are reported by the next receive
+            _ => Ok(true),
+        }
+    }
+}
+
+/// Mio sockets are always non-blocking, readiness is reported by the `mio::Poll`
+/// the socket is registered with.
+#[cfg(feature = "mio")]
+impl Transport for mio::net::UdpSocket {
+    type Addr = SocketAddr;
+
+    fn send_to(&self, buffer: &[u8], addr: &Self::Addr) -> io::Result<usize> {
+        mio::net::UdpSocket::send_to(self, buffer, *addr)
+    }
+
+    fn recv_from(&self, buffer: &mut [u8]) -> io::Result<(usize, Self::Addr)> {
+        mio::net::UdpSocket::recv_from(self, buffer)
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        mio::net::UdpSocket::local_addr(self)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        if nonblocking {
+            Ok(())
+        } else {
+            Err(io::ErrorKind::Unsupported.into())
+        }
+    }
+}
+
+/// Unix datagram sockets are addressed by path, `None` is an unnamed (unbound) socket.
+///
+/// Datagrams from unnamed sockets are received with a `None` source address,
+/// but can't be replied to.
+#[cfg(unix)]
+impl Transport for std::os::unix::net::UnixDatagram {
+    type Addr = Option<std::path::PathBuf>;
+
+    fn send_to(&self, buffer: &[u8], addr: &Self::Addr) -> io::Result<usize> {
+        let addr = addr.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "can't send to an unnamed unix socket",
+            )
+        })?;
+        std::os::unix::net::UnixDatagram::send_to(self, buffer, addr)
+    }
+
+    fn recv_from(&self, buffer: &mut [u8]) -> io::Result<(usize, Self::Addr)> {
+        let (len, addr) = std::os::unix::net::UnixDatagram::recv_from(self, buffer)?;
+        Ok((len, addr.as_pathname().map(ToOwned::to_owned)))
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        let addr = std::os::unix::net::UnixDatagram::local_addr(self)?;
+        Ok(addr.as_pathname().map(ToOwned::to_owned))
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        std::os::unix::net::UnixDatagram::set_nonblocking(self, nonblocking)
+    }
+}
+
+/// Address of an in-memory transport endpoint
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct MemoryAddr(u64);
+
+/// One end of an in-memory datagram channel pair
+pub struct MemoryTransport {
+    addr: MemoryAddr,
+    peer_addr: MemoryAddr,
+    tx: Sender<Vec<u8>>,
+    rx: Mutex<Receiver<Vec<u8>>>,
+    /// Datagram received by `wait_readable`
+    pending: Mutex<Option<Vec<u8>>>,
+    read_timeout: Mutex<Option<Duration>>,
+    nonblocking: Mutex<bool>,
+}
+
+impl MemoryTransport {
+    /// Creates two connected endpoints
+    pub fn pair() -> (Self, Self) {
+        static NEXT_ADDR: AtomicU64 = AtomicU64::new(0);
+
+        let left_addr = MemoryAddr(NEXT_ADDR.fetch_add(2, Ordering::Relaxed));
+        let right_addr = MemoryAddr(left_addr.0 + 1);
+
+        let (left_tx, right_rx) = mpsc::channel();
+        let (right_tx, left_rx) = mpsc::channel();
+
+        let make = |addr, peer_addr, tx, rx| Self {
+            addr,
+            peer_addr,
+            tx,
+            rx: Mutex::new(rx),
+            pending: Mutex::new(None),
+            read_timeout: Mutex::new(None),
+            nonblocking: Mutex::new(false),
+        };
+
+        (
+            make(left_addr, right_addr, left_tx, left_rx),
+            make(right_addr, left_addr, right_tx, right_rx),
+        )
+    }
+
+    pub fn peer_addr(&self) -> MemoryAddr {
+        self.peer_addr
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) {
+        *self.read_timeout.lock().unwrap() = timeout;
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) {
+        *self.nonblocking.lock().unwrap() = nonblocking;
+    }
+}
+
+impl Transport for MemoryTransport {
+    type Addr = MemoryAddr;
+
+    fn send_to(&self, buffer: &[u8], addr: &Self::Addr) -> io::Result<usize> {
+        if *addr != self.peer_addr {
+            return Err(io::ErrorKind::AddrNotAvailable.into());
+        }
+        self.tx
+            .send(buffer.to_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::ConnectionReset))?;
+        Ok(buffer.len())
+    }
+
+    fn recv_from(&self, buffer: &mut [u8]) -> io::Result<(usize, Self::Addr)> {
+        let rx = self.rx.lock().unwrap();
+
+        let datagram = if let Some(datagram) = self.pending.lock().unwrap().take() {
+            datagram
+        } else if *self.nonblocking.lock().unwrap() {
+            rx.try_recv().map_err(|e| match e {
+                TryRecvError::Empty => io::ErrorKind::WouldBlock,
+                TryRecvError::Disconnected => io::ErrorKind::ConnectionReset,
+            })?
+        } else if let Some(timeout) = *self.read_timeout.lock().unwrap() {
+            rx.recv_timeout(timeout).map_err(|e| match e {
+                RecvTimeoutError::Timeout => io::ErrorKind::WouldBlock,
+                RecvTimeoutError::Disconnected => io::ErrorKind::ConnectionReset,
+            })?
+        } else {
+            rx.recv()
+                .map_err(|_| io::Error::from(io::ErrorKind::ConnectionReset))?
+        };
+
+        // Excess bytes are discarded like for UDP
+        let len = datagram.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&datagram[..len]);
+        Ok((len, self.peer_addr))
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.addr)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        MemoryTransport::set_nonblocking(self, nonblocking);
+        Ok(())
+    }
+
+    fn wait_readable(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        let rx = self.rx.lock().unwrap();
+        let mut pending = self.pending.lock().unwrap();
+        MemoryTransport::set_nonblocking(self, true);
+        if pending.is_some() {
+            return Ok(true);
+        }
+
+        let datagram = match timeout {
+            Some(timeout) => match rx.recv_timeout(timeout) {
+                Ok(datagram) => datagram,
+                Err(RecvTimeoutError::Timeout) => return Ok(false),
+                // Reported by the next receive
+                Err(RecvTimeoutError::Disconnected) => return Ok(true),
+            },
+            None => match rx.recv() {
+                Ok(datagram) => datagram,
+                Err(_) => return Ok(true),
+            },
+        };
+        *pending = Some(datagram);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ping_pong<T: Transport>(left: &T, right: &T) {
+        let left_addr = left.local_addr().unwrap();
+        let right_addr = right.local_addr().unwrap();
+        let mut buffer = [0; 64];
+
+        assert_eq!(left.send_to(b"ping", &right_addr).unwrap(), 4);
+        let (len, from) = right.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"ping");
+        assert_eq!(from, left_addr);
+
+        assert_eq!(right.send_to(b"pong", &from).unwrap(), 4);
+        let (len, from) = left.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"pong");
+        assert_eq!(from, right_addr);
+    }
+
+    #[test]
+    fn udp_transport() {
+        let left = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let right = UdpSocket::bind("127.0.0.1:0").unwrap();
+        ping_pong(&left, &right);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_datagram_transport() {
+        use std::os::unix::net::UnixDatagram;
+
+        let dir = std::env::temp_dir().join(format!("tiny-udt-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let left = UnixDatagram::bind(dir.join("left.sock")).unwrap();
+        let right = UnixDatagram::bind(dir.join("right.sock")).unwrap();
+        ping_pong(&left, &right);
+
+        // Datagrams from unnamed sockets are reported with no address
+        let unnamed = UnixDatagram::unbound().unwrap();
+        assert_eq!(Transport::local_addr(&unnamed).unwrap(), None);
+        let right_addr = Transport::local_addr(&right).unwrap();
+        Transport::send_to(&unnamed, b"anonymous", &right_addr).unwrap();
+        let mut buffer = [0; 64];
+        let (len, from) = Transport::recv_from(&right, &mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"anonymous");
+        assert_eq!(from, None);
+        assert_eq!(
+            Transport::send_to(&right, b"reply", &from)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn memory_transport() {
+        let (left, right) = MemoryTransport::pair();
+        ping_pong(&left, &right);
+
+        left.set_nonblocking(true);
+        let mut buffer = [0; 64];
+        assert_eq!(
+            left.recv_from(&mut buffer).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        let thread = std::thread::spawn(move || {
+            let mut buffer = [0; 64];
+            let (len, from) = right.recv_from(&mut buffer).unwrap();
+            right.send_to(&buffer[..len], &from).unwrap();
+        });
+        left.send_to(b"echo", &left.peer_addr()).unwrap();
+        thread.join().unwrap();
+
+        left.set_nonblocking(false);
+        let (len, _) = left.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"echo");
+
+        assert_eq!(
+            left.send_to(b"closed", &left.peer_addr())
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::ConnectionReset
+        );
+    }
+}
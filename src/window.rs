@@ -1,84 +1,106 @@
 use std::time::{Duration, Instant};
 
-#[derive(Debug)]
-pub struct AckWindow<const SIZE: usize> {
-    items: Vec<AckWindowItem>,
-    /// Index of the latest ACK record
-    head: usize,
-    /// Index of the oldest ACK record
-    tail: usize,
+use crate::error::WindowError;
+use crate::packet::{SpeedAndBandwidth, HEADER_SIZE};
+use crate::seq::seq_offset;
+
+/// Minimum number of records in [`AckWindow`]
+pub const MIN_ACK_WINDOW_SIZE: usize = 1;
+/// Minimum number of samples in [`PacketTimeWindow`] windows (median filter needs at least 3)
+pub const MIN_TIME_WINDOW_SIZE: usize = 3;
+/// Default number of samples in the packet arrival window
+pub const DEFAULT_ARRIVAL_WINDOW_SIZE: usize = 16;
+/// Default number of samples in the probe window
+pub const DEFAULT_PROBE_WINDOW_SIZE: usize = 16;
+
+/// Window of sent ACKs waiting for ACK-2.
+///
+/// Records are indexed by their distance from the last stored ACK number modulo the
+/// window size, so a record is overwritten once `size` newer ACKs have been sent, also
+/// across the sequence number wraparound.
+///
+/// The window is sized at runtime by default, [`FixedAckWindow`] keeps the records inline.
+#[derive(Debug, Clone)]
+pub struct AckWindow<S = Box<[Option<AckRecord>]>> {
+    items: S,
+    /// Last stored ACK number
+    last_seq_no: u32,
+    /// Index of the last stored ACK number
+    last_index: usize,
 }
 
-impl<const SIZE: usize> AckWindow<SIZE> {
-    pub fn new() -> Self {
-        let mut window = Self {
-            items: vec![Default::default(); SIZE],
-            head: 0,
-            tail: 0,
-        };
-        window.items[0].seq_no = u32::MAX;
+/// Heap-free [`AckWindow`] with the size fixed at compile time
+pub type FixedAckWindow<const SIZE: usize> = AckWindow<[Option<AckRecord>; SIZE]>;
 
-        window
+impl AckWindow {
+    pub fn new(size: usize) -> Result<Self, WindowError> {
+        check_size(size, MIN_ACK_WINDOW_SIZE)?;
+
+        Ok(Self {
+            items: vec![None; size].into_boxed_slice(),
+            last_seq_no: 0,
+            last_index: 0,
+        })
     }
+}
 
-    /// Write an ACK record into the window
-    pub fn store(&mut self, seq_no: u32, data_seq_no: u32) {
-        unsafe {
-            *self.items.get_unchecked_mut(self.head) = AckWindowItem {
-                timestamp: Instant::now(),
-                seq_no,
-                data_seq_no,
-            }
-        };
+impl<const SIZE: usize> FixedAckWindow<SIZE> {
+    pub fn new_fixed() -> Self {
+        const { assert!(SIZE >= MIN_ACK_WINDOW_SIZE) };
 
-        self.head = (self.head + 1) % SIZE;
-        if self.head == self.tail {
-            self.tail = (self.tail + 1) % SIZE;
+        Self {
+            items: [None; SIZE],
+            last_seq_no: 0,
+            last_index: 0,
         }
     }
+}
 
-    /// Search the ACK-2 "seq" in the window, find out the DATA "ack" and calculate RTT
-    pub fn acknowledge(&mut self, seq_no: u32) -> Option<Acknowledgement> {
-        // Head has not exceeded the physical boundary of the window
-        if self.head >= self.tail {
-            for i in self.tail..self.head {
-                let item = unsafe { self.items.get_unchecked_mut(i) };
-                if seq_no != item.seq_no {
-                    continue;
-                }
-
-                let ack = item.make_ack();
-                self.bump_or_reset(i);
-                return Some(ack);
-            }
+impl<const SIZE: usize> Default for FixedAckWindow<SIZE> {
+    fn default() -> Self {
+        Self::new_fixed()
+    }
+}
 
-            return None;
-        }
+impl<S> AckWindow<S>
+where
+    S: AsRef<[Option<AckRecord>]> + AsMut<[Option<AckRecord>]>,
+{
+    /// Number of records in the window
+    pub fn size(&self) -> usize {
+        self.items.as_ref().len()
+    }
 
-        // Head has exceeded the physical window boundary, so it is behind tail
-        for i in self.tail..(self.head + SIZE) {
-            let item = unsafe { self.items.get_unchecked_mut(i % SIZE) };
-            if seq_no != item.seq_no {
-                continue;
-            }
+    /// Write an ACK record sent at `now` into the window
+    pub fn store(&mut self, seq_no: u32, data_seq_no: u32, now: Instant) {
+        let index = self.index(seq_no);
+        self.last_seq_no = seq_no;
+        self.last_index = index;
+        self.items.as_mut()[index] = Some(AckRecord {
+            timestamp: now,
+            seq_no,
+            data_seq_no,
+        });
+    }
 
-            let ack = item.make_ack();
-            self.bump_or_reset(i);
-            return Some(ack);
+    /// Search the ACK-2 "seq" in the window, find out the DATA "ack" and calculate RTT
+    /// from the ACK-2 arrival time `now`.
+    ///
+    /// Each record is matched at most once.
+    pub fn acknowledge(&mut self, seq_no: u32, now: Instant) -> Option<Acknowledgement> {
+        let index = self.index(seq_no);
+        let slot = &mut self.items.as_mut()[index];
+        match slot {
+            Some(item) if item.seq_no == seq_no => slot.take().map(|item| item.make_ack(now)),
+            _ => None,
         }
-
-        None
     }
 
-    #[inline(always)]
-    fn bump_or_reset(&mut self, i: usize) {
-        if i + 1 == self.head {
-            self.head = 0;
-            self.tail = 0;
-            self.items[0].seq_no = u32::MAX;
-        } else {
-            self.tail = (i + 1) % SIZE;
-        }
+    /// Slot of `seq_no`. Plain `seq_no % size` would break the order of the slots when
+    /// the ACK number wraps around unless the size divides the sequence space.
+    fn index(&self, seq_no: u32) -> usize {
+        let offset = seq_offset(self.last_seq_no, seq_no) as i64;
+        (self.last_index as i64 + offset).rem_euclid(self.size() as i64) as usize
     }
 }
 
@@ -90,8 +112,9 @@ pub struct Acknowledgement {
     pub rtt: Duration,
 }
 
-#[derive(Debug, Clone)]
-struct AckWindowItem {
+/// Sent ACK record
+#[derive(Debug, Copy, Clone)]
+pub struct AckRecord {
     /// The timestamp when the ACK was sent
     timestamp: Instant,
     /// Seq. No. for the ACK packet
@@ -100,169 +123,564 @@ struct AckWindowItem {
     data_seq_no: u32,
 }
 
-impl AckWindowItem {
+impl AckRecord {
     #[inline(always)]
-    fn make_ack(&self) -> Acknowledgement {
+    fn make_ack(&self, now: Instant) -> Acknowledgement {
         Acknowledgement {
             data_seq_no: self.data_seq_no,
-            rtt: Instant::now().saturating_duration_since(self.timestamp),
+            rtt: now.saturating_duration_since(self.timestamp),
         }
     }
 }
 
-impl Default for AckWindowItem {
-    fn default() -> Self {
+/// Packet interval together with the size of the packet which ended it.
+///
+/// Samples are ordered by interval first.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub struct PacketSample {
+    interval: Duration,
+    /// Payload size (in bytes)
+    size: u32,
+}
+
+impl PacketSample {
+    const fn new(interval: Duration) -> Self {
+        Self { interval, size: 0 }
+    }
+
+    fn micros(&self) -> u64 {
+        self.interval.as_micros() as u64
+    }
+
+    /// Size on the wire including the UDT header
+    fn bytes(&self) -> u64 {
+        self.size as u64 + HEADER_SIZE as u64
+    }
+}
+
+/// Ring buffer of samples with a sorted copy maintained on every insertion,
+/// so the median and the median filter range never require copying the window
+#[derive(Debug, Clone)]
+struct MedianWindow<S> {
+    /// Samples in arrival order
+    ring: S,
+    /// The same samples in ascending order
+    sorted: S,
+    /// Position of the oldest sample in `ring`
+    index: usize,
+}
+
+impl<S> MedianWindow<S>
+where
+    S: AsRef<[PacketSample]> + AsMut<[PacketSample]>,
+{
+    /// `ring` and `sorted` must contain the same samples
+    fn new(ring: S, sorted: S) -> Self {
+        debug_assert!(sorted.as_ref().is_sorted());
+
         Self {
-            timestamp: Instant::now(),
-            seq_no: 0,
-            data_seq_no: 0,
+            ring,
+            sorted,
+            index: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.ring.as_ref().len()
+    }
+
+    /// Replaces the oldest sample
+    fn push(&mut self, sample: PacketSample) {
+        let len = self.len();
+        let old = std::mem::replace(&mut self.ring.as_mut()[self.index], sample);
+        self.index = (self.index + 1) % len;
+
+        let sorted = self.sorted.as_mut();
+        let from = sorted
+            .binary_search(&old)
+            .expect("every sample in the ring is also in the sorted window");
+        let to = sorted.partition_point(|item| *item < sample);
+        if to > from {
+            sorted[from..to].rotate_left(1);
+            sorted[to - 1] = sample;
+        } else {
+            sorted[to..=from].rotate_right(1);
+            sorted[to] = sample;
         }
     }
+
+    fn median(&self) -> PacketSample {
+        let sorted = self.sorted.as_ref();
+        sorted[sorted.len() / 2]
+    }
+
+    /// Samples with the interval within `lower..upper` microseconds
+    fn range(&self, lower: u64, upper: u64) -> &[PacketSample] {
+        let sorted = self.sorted.as_ref();
+        let start = sorted.partition_point(|sample| sample.micros() < lower);
+        let end = sorted.partition_point(|sample| sample.micros() < upper);
+        &sorted[start..end.max(start)]
+    }
+}
+
+/// Rate in packets and bytes per second
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Rate {
+    pub packets_per_sec: u64,
+    /// Includes the UDT header of every packet
+    pub bytes_per_sec: u64,
 }
 
-#[derive(Debug)]
-pub struct PacketTimeWindow<const ARRIVAL_SIZE: usize, const PROBE_SIZE: usize> {
+/// Packet arrival and probe interval history used for speed and bandwidth estimation.
+///
+/// The windows are sized at runtime by default, [`FixedPacketTimeWindow`] keeps them inline.
+/// Median filtering takes `O(log n)` plus the number of samples within the filter range,
+/// recording a sample takes `O(n)` without allocation.
+#[derive(Debug, Clone)]
+pub struct PacketTimeWindow<A = Box<[PacketSample]>, P = Box<[PacketSample]>> {
     /// Packet information window
-    packet_window: Vec<Duration>,
-    /// Position pointer of the packet info window
-    packet_window_index: usize,
+    packet_window: MedianWindow<A>,
 
     /// Record inter-packet time for probing packet pairs
-    probe_window: Vec<Duration>,
-    /// Position pointer to the probing window
-    probe_window_index: usize,
+    probe_window: MedianWindow<P>,
 
     /// Last packet sending time
-    last_sent_time: Instant,
+    last_sent_time: Option<Instant>,
     /// Minimum packet sending interval
     min_packet_sending_interval: Duration,
 
     /// Last packet arrival time
-    last_arrival_time: Instant,
-    /// Current packet arrival time
-    current_arrival_time: Instant,
+    last_arrival_time: Option<Instant>,
     /// Arrival time of the first probing packet
-    probe_time: Instant,
+    probe_time: Option<Instant>,
+    /// One-way delay of the latest data packet (in microseconds)
+    one_way_delay: Option<u32>,
+}
+
+/// Heap-free [`PacketTimeWindow`] with the sizes fixed at compile time
+pub type FixedPacketTimeWindow<const ARRIVAL_SIZE: usize, const PROBE_SIZE: usize> =
+    PacketTimeWindow<[PacketSample; ARRIVAL_SIZE], [PacketSample; PROBE_SIZE]>;
+
+const INITIAL_PACKET_SAMPLE: PacketSample = PacketSample::new(Duration::from_secs(1));
+const INITIAL_PROBE_SAMPLE: PacketSample = PacketSample::new(Duration::from_millis(1));
+
+impl PacketTimeWindow {
+    pub fn new(arrival_size: usize, probe_size: usize) -> Result<Self, WindowError> {
+        check_size(arrival_size, MIN_TIME_WINDOW_SIZE)?;
+        check_size(probe_size, MIN_TIME_WINDOW_SIZE)?;
+
+        let packet_window = vec![INITIAL_PACKET_SAMPLE; arrival_size].into_boxed_slice();
+        let probe_window = vec![INITIAL_PROBE_SAMPLE; probe_size].into_boxed_slice();
+        Ok(Self::with_windows(
+            MedianWindow::new(packet_window.clone(), packet_window),
+            MedianWindow::new(probe_window.clone(), probe_window),
+        ))
+    }
 }
 
 impl<const ARRIVAL_SIZE: usize, const PROBE_SIZE: usize>
-    PacketTimeWindow<ARRIVAL_SIZE, PROBE_SIZE>
+    FixedPacketTimeWindow<ARRIVAL_SIZE, PROBE_SIZE>
 {
-    pub fn new() -> Self {
-        let last_arrival_time = Instant::now();
+    pub fn new_fixed() -> Self {
+        const {
+            assert!(ARRIVAL_SIZE >= MIN_TIME_WINDOW_SIZE);
+            assert!(PROBE_SIZE >= MIN_TIME_WINDOW_SIZE);
+        };
 
+        let packet_window = [INITIAL_PACKET_SAMPLE; ARRIVAL_SIZE];
+        let probe_window = [INITIAL_PROBE_SAMPLE; PROBE_SIZE];
+        Self::with_windows(
+            MedianWindow::new(packet_window, packet_window),
+            MedianWindow::new(probe_window, probe_window),
+        )
+    }
+}
+
+impl<const ARRIVAL_SIZE: usize, const PROBE_SIZE: usize> Default
+    for FixedPacketTimeWindow<ARRIVAL_SIZE, PROBE_SIZE>
+{
+    fn default() -> Self {
+        Self::new_fixed()
+    }
+}
+
+impl<A, P> PacketTimeWindow<A, P>
+where
+    A: AsRef<[PacketSample]> + AsMut<[PacketSample]>,
+    P: AsRef<[PacketSample]> + AsMut<[PacketSample]>,
+{
+    fn with_windows(packet_window: MedianWindow<A>, probe_window: MedianWindow<P>) -> Self {
         Self {
-            packet_window: vec![Duration::from_secs(1); ARRIVAL_SIZE],
-            packet_window_index: 0,
-            probe_window: vec![Duration::from_millis(1); PROBE_SIZE],
-            probe_window_index: 0,
-            last_sent_time: last_arrival_time,
+            packet_window,
+            probe_window,
+            last_sent_time: None,
             min_packet_sending_interval: Default::default(),
-            last_arrival_time,
-            current_arrival_time: last_arrival_time,
-            probe_time: last_arrival_time,
+            last_arrival_time: None,
+            probe_time: None,
+            one_way_delay: None,
         }
     }
 
+    /// Number of samples in the packet arrival window
+    pub fn arrival_size(&self) -> usize {
+        self.packet_window.len()
+    }
+
+    /// Number of samples in the probe window
+    pub fn probe_size(&self) -> usize {
+        self.probe_window.len()
+    }
+
     #[allow(unused)]
     pub fn min_packet_sending_interval(&self) -> Duration {
         self.min_packet_sending_interval
     }
 
-    pub fn get_packet_receive_speed(&self) -> u64 {
-        // SAFETY: `packet_window` is initialized right after that
-        let mut packet_window =
-            unsafe { std::mem::MaybeUninit::<[Duration; ARRIVAL_SIZE]>::uninit().assume_init() };
-        packet_window.copy_from_slice(&self.packet_window);
+    /// Packet receiving rate, zero if the arrival intervals are too scattered
+    pub fn get_packet_receive_speed(&self) -> Rate {
+        let median = self.packet_window.median().micros();
+        let samples = self.packet_window.range(median >> 3, median << 3);
+
+        let count = samples.len();
+        // Rates use the exact intervals, whole microseconds are too coarse on fast links
+        let total_duration = samples
+            .iter()
+            .map(|sample| sample.interval)
+            .sum::<Duration>()
+            .as_secs_f64();
+        let total_bytes: u64 = samples.iter().map(PacketSample::bytes).sum();
+
+        if count > (self.arrival_size() >> 1) {
+            let average_duration = total_duration / count as f64;
+            Rate {
+                packets_per_sec: (1.0 / average_duration) as u64,
+                bytes_per_sec: (total_bytes as f64 / total_duration) as u64,
+            }
+        } else {
+            Rate::default()
+        }
+    }
 
-        let median = *packet_window.select_nth_unstable(ARRIVAL_SIZE / 2).1;
-        let median = median.as_micros() as u64;
+    /// Estimated link capacity from packet pair probes
+    pub fn get_bandwidth(&self) -> Rate {
+        let median = self.probe_window.median();
+        let median_duration = median.micros();
+        let samples = self
+            .probe_window
+            .range(median_duration >> 3, median_duration << 3);
+
+        // The median is counted once more to avoid division by zero
+        let count = samples.len() + 1;
+        let total_duration = (median.interval
+            + samples
+                .iter()
+                .map(|sample| sample.interval)
+                .sum::<Duration>())
+        .as_secs_f64();
+        let total_bytes = median.bytes() + samples.iter().map(PacketSample::bytes).sum::<u64>();
+
+        let average_duration = total_duration / count as f64;
+        Rate {
+            packets_per_sec: (1.0 / average_duration) as u64,
+            bytes_per_sec: (total_bytes as f64 / total_duration) as u64,
+        }
+    }
+
+    /// Receiving rate and link capacity for ACK
+    pub fn speed_and_bandwidth(&self) -> SpeedAndBandwidth {
+        let speed = self.get_packet_receive_speed();
+        let bandwidth = self.get_bandwidth();
+        let saturate = |value: u64| value.min(u32::MAX as u64) as u32;
+
+        SpeedAndBandwidth {
+            speed: saturate(speed.packets_per_sec),
+            bandwidth: saturate(bandwidth.packets_per_sec),
+            bytes: Some((
+                saturate(speed.bytes_per_sec),
+                saturate(bandwidth.bytes_per_sec),
+            )),
+        }
+    }
 
-        let mut count = 0;
-        let mut total_duration = 0;
-        let upper = median << 3;
-        let lower = median >> 3;
+    /// One-way delay of the latest data packet for ACK, see [`Self::on_packet_timestamp`]
+    pub fn one_way_delay(&self) -> Option<u32> {
+        self.one_way_delay
+    }
 
-        for duration in packet_window {
-            let duration = duration.as_micros() as u64;
-            if (lower..upper).contains(&duration) {
-                count += 1;
-                total_duration += duration;
+    #[allow(unused)]
+    pub fn on_packet_sent(&mut self, current_time: Instant) {
+        if let Some(last_sent_time) = self.last_sent_time {
+            let interval = current_time.saturating_duration_since(last_sent_time);
+            if (interval < self.min_packet_sending_interval) && !interval.is_zero() {
+                self.min_packet_sending_interval = interval;
             }
         }
 
-        if count > (ARRIVAL_SIZE >> 1) {
-            let average_duration = total_duration as f64 / count as f64;
-            (1000000.0f64 / average_duration) as u64
-        } else {
-            0
+        self.last_sent_time = Some(current_time);
+    }
+
+    /// Records arrival of a data packet with `size` bytes of payload at `now`.
+    ///
+    /// The first packet only starts the measurement.
+    pub fn on_packet_arrival(&mut self, now: Instant, size: usize) {
+        if let Some(last_arrival_time) = self.last_arrival_time {
+            self.packet_window.push(PacketSample {
+                interval: now.saturating_duration_since(last_arrival_time),
+                size: size as u32,
+            });
         }
+        self.last_arrival_time = Some(now);
     }
 
-    pub fn get_bandwidth(&self) -> u64 {
-        // SAFETY: `probe_window` is initialized right after that
-        let mut probe_window =
-            unsafe { std::mem::MaybeUninit::<[Duration; PROBE_SIZE]>::uninit().assume_init() };
-        probe_window.copy_from_slice(&self.probe_window);
+    /// Records the one-way delay of a data packet from its header timestamp and the local
+    /// timestamp of the arrival (both in microseconds, wrapping around).
+    ///
+    /// The delay includes the clock offset between the peers.
+    pub fn on_packet_timestamp(&mut self, timestamp: u32, arrival_timestamp: u32) {
+        self.one_way_delay = Some(arrival_timestamp.wrapping_sub(timestamp));
+    }
 
-        let median = *probe_window.select_nth_unstable(PROBE_SIZE / 2).1;
-        let median = median.as_micros() as u64;
+    /// Records arrival of the first probe packet at `now`
+    pub fn probe1_arrival(&mut self, now: Instant) {
+        self.probe_time = Some(now);
+    }
 
-        let mut count = 1;
-        let mut total_duration = median;
-        let upper = median << 3;
-        let lower = median >> 3;
+    /// Records arrival of the second probe packet with `size` bytes of payload at `now`.
+    ///
+    /// Ignored if the first packet of the pair was lost.
+    pub fn probe2_arrival(&mut self, now: Instant, size: usize) {
+        if let Some(probe_time) = self.probe_time.take() {
+            self.on_probe(now.saturating_duration_since(probe_time), size);
+        }
+    }
 
-        for duration in probe_window {
-            let duration = duration.as_micros() as u64;
-            if (lower..upper).contains(&duration) {
-                count += 1;
-                total_duration += duration;
-            }
+    /// Records the arrival gap of a probe packet pair and the payload size of the second packet
+    pub fn on_probe(&mut self, interval: Duration, size: usize) {
+        self.probe_window.push(PacketSample {
+            interval,
+            size: size as u32,
+        });
+    }
+}
+
+fn check_size(size: usize, min: usize) -> Result<(), WindowError> {
+    if size < min {
+        return Err(WindowError::TooSmall { size, min });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::seq::{seq_inc, MAX_SEQ_NO};
+
+    #[test]
+    fn ack_window_wraparound() {
+        let mut window = AckWindow::new(4).unwrap();
+        let now = Instant::now();
+
+        // `u32::MAX` is not special
+        assert!(window.acknowledge(u32::MAX, now).is_none());
+        window.store(u32::MAX, 10, now);
+        assert_eq!(window.acknowledge(u32::MAX, now).unwrap().data_seq_no, 10);
+        assert!(window.acknowledge(u32::MAX, now).is_none());
+
+        let mut seq_no = MAX_SEQ_NO - 1;
+        for data_seq_no in 0..4 {
+            window.store(seq_no, data_seq_no, now);
+            seq_no = seq_inc(seq_no);
         }
+        assert_eq!(window.acknowledge(0, now).unwrap().data_seq_no, 2);
+        assert_eq!(window.acknowledge(MAX_SEQ_NO, now).unwrap().data_seq_no, 1);
+        assert!(window.acknowledge(0, now).is_none());
+
+        // Oldest record is overwritten
+        window.store(MAX_SEQ_NO - 5, 100, now);
+        assert!(window.acknowledge(MAX_SEQ_NO - 1, now).is_none());
+        assert_eq!(window.acknowledge(1, now).unwrap().data_seq_no, 3);
+        assert_eq!(
+            window.acknowledge(MAX_SEQ_NO - 5, now).unwrap().data_seq_no,
+            100
+        );
+    }
+
+    #[test]
+    fn ack_window_wraparound_with_odd_size() {
+        let mut window = AckWindow::new(3).unwrap();
+        let now = Instant::now();
 
-        let average_duration = total_duration as f64 / count as f64;
-        (1000000.0f64 / average_duration) as u64
+        let mut seq_no = MAX_SEQ_NO - 2;
+        for data_seq_no in 0..4 {
+            window.store(seq_no, data_seq_no, now);
+            seq_no = seq_inc(seq_no);
+        }
+        // ACK 0 replaced the oldest record only
+        assert!(window.acknowledge(MAX_SEQ_NO - 2, now).is_none());
+        assert_eq!(
+            window.acknowledge(MAX_SEQ_NO - 1, now).unwrap().data_seq_no,
+            1
+        );
+        assert_eq!(window.acknowledge(MAX_SEQ_NO, now).unwrap().data_seq_no, 2);
+        assert_eq!(window.acknowledge(0, now).unwrap().data_seq_no, 3);
     }
 
-    #[allow(unused)]
-    pub fn on_packet_sent(&mut self, current_time: Instant) {
-        let interval = current_time.saturating_duration_since(self.last_sent_time);
-        if (interval < self.min_packet_sending_interval) && !interval.is_zero() {
-            self.min_packet_sending_interval = interval;
+    #[test]
+    fn window_sizes_are_validated() {
+        assert!(matches!(
+            AckWindow::new(0),
+            Err(WindowError::TooSmall { size: 0, min: 1 })
+        ));
+        assert_eq!(AckWindow::new(1).unwrap().size(), 1);
+
+        assert!(PacketTimeWindow::new(2, 16).is_err());
+        assert!(PacketTimeWindow::new(16, 2).is_err());
+        let window = PacketTimeWindow::new(32, 8).unwrap();
+        assert_eq!((window.arrival_size(), window.probe_size()), (32, 8));
+    }
+
+    #[test]
+    fn fixed_windows_match_runtime_windows() {
+        let mut fixed = FixedAckWindow::<8>::new_fixed();
+        let mut runtime = AckWindow::new(8).unwrap();
+        let now = Instant::now();
+        for seq_no in 0..20 {
+            fixed.store(seq_no, seq_no + 100, now);
+            runtime.store(seq_no, seq_no + 100, now);
+        }
+        for seq_no in 0..20 {
+            assert_eq!(
+                fixed.acknowledge(seq_no, now).map(|ack| ack.data_seq_no),
+                runtime.acknowledge(seq_no, now).map(|ack| ack.data_seq_no)
+            );
         }
 
-        self.last_sent_time = current_time;
+        let fixed = FixedPacketTimeWindow::<16, 16>::default();
+        let runtime = PacketTimeWindow::new(16, 16).unwrap();
+        assert_eq!(
+            fixed.get_packet_receive_speed(),
+            runtime.get_packet_receive_speed()
+        );
+        assert_eq!(fixed.get_bandwidth(), runtime.get_bandwidth());
     }
 
-    pub fn on_packet_arrival(&mut self) {
-        self.current_arrival_time = Instant::now();
-        // SAFETY: `packet_window` size is always ARRIVAL_SIZE and `packet_window_index`
-        // is always incremented with modulo ARRIVAL_SIZE
-        unsafe {
-            *self
-                .packet_window
-                .get_unchecked_mut(self.packet_window_index) = self
-                .current_arrival_time
-                .saturating_duration_since(self.last_arrival_time);
+    #[test]
+    fn byte_rates_follow_packet_sizes() {
+        let mut window = FixedPacketTimeWindow::<16, 16>::new_fixed();
+        assert_eq!(window.get_packet_receive_speed().packets_per_sec, 1);
+
+        // One outlier is filtered out
+        window.packet_window.push(PacketSample {
+            interval: Duration::from_secs(1),
+            size: 1_000_000,
+        });
+        // 1 ms apart, alternating small and large packets
+        for i in 1..16 {
+            window.packet_window.push(PacketSample {
+                interval: Duration::from_millis(1),
+                size: if i % 2 == 0 { 84 } else { 1484 },
+            });
+        }
+
+        let speed = window.get_packet_receive_speed();
+        assert_eq!(speed.packets_per_sec, 1000);
+        // 7 packets of 100 bytes and 8 packets of 1500 bytes (with headers) in 15 ms
+        assert_eq!(speed.bytes_per_sec, (7 * 100 + 8 * 1500) * 1000 / 15);
+
+        for _ in 0..16 {
+            window.probe_window.push(PacketSample {
+                interval: Duration::from_micros(100),
+                size: 1484,
+            });
         }
-        self.packet_window_index = (self.packet_window_index + 1) % ARRIVAL_SIZE;
-        self.last_arrival_time = self.current_arrival_time;
+        let bandwidth = window.get_bandwidth();
+        assert_eq!(bandwidth.packets_per_sec, 10_000);
+        assert_eq!(bandwidth.bytes_per_sec, 15_000_000);
+
+        let rates = window.speed_and_bandwidth();
+        assert_eq!((rates.speed, rates.bandwidth), (1000, 10_000));
+        assert_eq!(rates.bytes, Some((speed.bytes_per_sec as u32, 15_000_000)));
     }
 
-    pub fn probe1_arrival(&mut self) {
-        self.probe_time = Instant::now();
+    #[test]
+    fn arrival_times_come_from_caller() {
+        let mut window = FixedPacketTimeWindow::<16, 16>::new_fixed();
+        let start = Instant::now();
+
+        // The first packet only starts the measurement
+        window.on_packet_arrival(start + Duration::from_secs(10), 1484);
+        for i in 1..=16 {
+            window.on_packet_arrival(
+                start + Duration::from_secs(10) + Duration::from_millis(i),
+                1484,
+            );
+        }
+        let speed = window.get_packet_receive_speed();
+        assert_eq!(speed.packets_per_sec, 1000);
+        assert_eq!(speed.bytes_per_sec, 1_500_000);
+
+        // Both clocks wrap around independently
+        assert_eq!(window.one_way_delay(), None);
+        window.on_packet_timestamp(u32::MAX - 10, 20);
+        assert_eq!(window.one_way_delay(), Some(31));
+
+        // The second probe is ignored without the first one
+        window.probe2_arrival(start, 1484);
+        for i in 0..16 {
+            let probe_time = start + Duration::from_millis(i);
+            window.probe1_arrival(probe_time);
+            window.probe2_arrival(probe_time + Duration::from_micros(100), 1484);
+        }
+        window.probe2_arrival(start + Duration::from_secs(1), 1484);
+        assert_eq!(window.get_bandwidth().packets_per_sec, 10_000);
     }
 
-    pub fn probe2_arrival(&mut self) {
-        self.current_arrival_time = Instant::now();
-        // SAFETY: `probe_window` size is always PROBE_SIZE and `probe_window_index`
-        // is always incremented with modulo PROBE_SIZE
-        unsafe {
-            *self.probe_window.get_unchecked_mut(self.probe_window_index) = self
-                .current_arrival_time
-                .saturating_duration_since(self.probe_time);
+    /// Median filter over a sorted copy of the window, as it was done before
+    fn naive_receive_speed(window: &[PacketSample]) -> Rate {
+        let mut window = window.to_vec();
+        window.sort();
+        let median = window[window.len() / 2].micros();
+
+        let samples = window
+            .iter()
+            .filter(|sample| ((median >> 3)..(median << 3)).contains(&sample.micros()))
+            .collect::<Vec<_>>();
+        let total_duration: Duration = samples.iter().map(|sample| sample.interval).sum();
+        let total_duration = total_duration.as_secs_f64();
+        let total_bytes: u64 = samples.iter().map(|sample| sample.bytes()).sum();
+
+        if samples.len() > window.len() / 2 {
+            Rate {
+                packets_per_sec: (1.0 / (total_duration / samples.len() as f64)) as u64,
+                bytes_per_sec: (total_bytes as f64 / total_duration) as u64,
+            }
+        } else {
+            Rate::default()
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn median_window_matches_naive(
+            samples in proptest::collection::vec((0u64..5000, 0u32..1500), 1..200),
+        ) {
+            let mut window = FixedPacketTimeWindow::<7, 16>::new_fixed();
+            let mut naive = [INITIAL_PACKET_SAMPLE; 7];
+
+            for (i, (micros, size)) in samples.into_iter().enumerate() {
+                let sample = PacketSample {
+                    interval: Duration::from_micros(micros),
+                    size,
+                };
+                window.packet_window.push(sample);
+                naive[i % naive.len()] = sample;
+
+                prop_assert_eq!(window.packet_window.ring, naive);
+                prop_assert!(window.packet_window.sorted.is_sorted());
+                prop_assert_eq!(window.get_packet_receive_speed(), naive_receive_speed(&naive));
+            }
         }
-        self.probe_window_index = (self.probe_window_index + 1) % PROBE_SIZE;
     }
 }